@@ -0,0 +1,582 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::constants;
+use crate::game::emitter::{EmitterConfig, EmitterShape};
+use crate::game::equation_of_state::EquationOfState;
+use crate::game::force_model::ForceModel;
+use crate::game::integrator::Integrator;
+use crate::game::kernel::KernelType;
+use crate::game::material::Material;
+use crate::game::obstacle::Obstacle;
+use crate::game::rigid_body::{self, BodyShape, RigidBodyConfig};
+use crate::game::solver::SolverType;
+use crate::game::vector::Vector;
+use crate::game::walls::{WallMode, Walls};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "could not read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "could not parse config: {}", e),
+            ConfigError::Invalid(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+/// Physical and visual parameters of a simulation run.
+///
+/// Every field is optional in TOML; missing fields fall back to the values in
+/// [`constants`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationConfig {
+    pub fps: u64,
+    pub window_size: (u32, u32),
+
+    /// Length of one fixed physics step in seconds, independent of `fps`.
+    pub timestep: f32,
+    pub substeps: u32,
+    pub max_steps_per_frame: u32,
+    /// Split every step into substeps sized by the CFL and force criteria
+    /// instead of `substeps` equal ones.
+    pub adaptive_timestep: bool,
+    /// Safety factor `λ` of the adaptive timestep.
+    pub cfl_factor: f32,
+    pub min_timestep: f32,
+    pub max_timestep: f32,
+    /// Only the explicit solver supports integrators other than
+    /// `Integrator::SymplecticEuler`.
+    pub integrator: Integrator,
+    /// Worker threads for the `parallel` feature; 0 uses one per core.
+    pub threads: usize,
+
+    pub gravity: f32,
+    pub collision_damping: f32,
+    pub drag_coefficient: f32,
+    /// Behaviour of each window side, as `[walls.<side>]` tables.
+    pub walls: Walls,
+    /// Static colliders inside the window, as `[[obstacles]]` tables. Particles
+    /// bounce off them with `collision_damping`.
+    pub obstacles: Vec<Obstacle>,
+    /// Dynamic bodies pushed around by the fluid, as `[[rigid_bodies]]` tables.
+    pub rigid_bodies: Vec<RigidBodyConfig>,
+    /// Particle sources, as `[[emitters]]` tables.
+    pub emitters: Vec<EmitterConfig>,
+    /// Regions that remove the particles entering them, as `[[drains]]` tables
+    /// with the same shapes as `obstacles`.
+    pub drains: Vec<Obstacle>,
+
+    /// Particles created at the start and on reset.
+    pub particle_amt: usize,
+    /// Hard cap on the particle count; emitters and `Simulation::add_particle`
+    /// stop adding particles once it is reached.
+    pub max_particles: usize,
+    pub particle_radius: u32,
+    pub particle_spacing: u32,
+
+    pub smoothing_radius: u32,
+    /// Kernel for density and the pressure gradient.
+    pub kernel: KernelType,
+    pub viscosity_kernel: KernelType,
+    /// Rest density of the fluid, roughly `1 / particle_spacing^2` for the
+    /// default mass of 1.
+    pub density_floor: f32,
+    pub pressure_constant: f32,
+    pub equation_of_state: EquationOfState,
+    pub clamp_negative_pressure: bool,
+    pub force_model: ForceModel,
+    /// Stiffness of the near-pressure term of `ForceModel::DoubleDensity`.
+    pub near_pressure_constant: f32,
+    pub viscosity_constant: f32,
+    /// Akinci cohesion and curvature coefficient; 0 disables surface tension.
+    /// Uses the material rest density.
+    pub surface_tension: f32,
+    /// Fluids present in the scene, as `[[materials]]` tables. When empty, a
+    /// single material is derived from `density_floor`, `viscosity_constant`
+    /// and `pressure_constant`; see [`SimulationConfig::resolve_materials`]
+    /// and [`Material::default_rest_density`].
+    pub materials: Vec<Material>,
+
+    pub solver: SolverType,
+    /// Upper bound on pressure iterations per step for the iterative solvers.
+    pub max_iterations: u32,
+    /// Average relative compression at which the iterative solvers stop.
+    pub density_error_tolerance: f32,
+    /// XSPH velocity smoothing of `SolverType::Pbf`, as a fraction of the
+    /// difference to the neighbourhood average.
+    pub xsph_viscosity: f32,
+
+    pub cursor_radius: f32,
+    pub cursor_constant: f32,
+
+    /// Seed for every random choice made by the simulation. `None` picks a
+    /// fresh seed per run; the chosen one is reported by `Simulation::seed`.
+    pub seed: Option<u64>,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            fps: constants::FPS,
+            window_size: constants::WINDOW_SIZE,
+            timestep: constants::TIMESTEP,
+            substeps: constants::SUBSTEPS,
+            max_steps_per_frame: constants::MAX_STEPS_PER_FRAME,
+            adaptive_timestep: false,
+            cfl_factor: constants::CFL_FACTOR,
+            min_timestep: constants::MIN_TIMESTEP,
+            max_timestep: constants::MAX_TIMESTEP,
+            integrator: Integrator::SymplecticEuler,
+            threads: constants::THREADS,
+            gravity: constants::GRAVITY,
+            collision_damping: constants::COLLISION_DAMPING,
+            drag_coefficient: constants::DRAG_COEFFICIENT,
+            walls: Walls::default(),
+            obstacles: Vec::new(),
+            rigid_bodies: Vec::new(),
+            emitters: Vec::new(),
+            drains: Vec::new(),
+            particle_amt: constants::PARTICLE_AMT,
+            max_particles: constants::MAX_PARTICLES,
+            particle_radius: constants::PARTICLE_RADIUS,
+            particle_spacing: constants::PARTICLE_SPACING,
+            smoothing_radius: constants::SMOOTHING_RADIUS,
+            kernel: KernelType::SpikyPow2,
+            viscosity_kernel: KernelType::Poly6,
+            density_floor: constants::DENSITY_FLOOR,
+            pressure_constant: constants::PRESSURE_CONSTANT,
+            equation_of_state: EquationOfState::Linear,
+            clamp_negative_pressure: false,
+            force_model: ForceModel::Pressure,
+            near_pressure_constant: constants::NEAR_PRESSURE_CONSTANT,
+            viscosity_constant: constants::VISCOSITY_CONSTANT,
+            surface_tension: constants::SURFACE_TENSION,
+            materials: Vec::new(),
+            solver: SolverType::Explicit,
+            max_iterations: constants::MAX_ITERATIONS,
+            density_error_tolerance: constants::DENSITY_ERROR_TOLERANCE,
+            xsph_viscosity: constants::XSPH_VISCOSITY,
+            cursor_radius: constants::CURSOR_RADIUS,
+            cursor_constant: constants::CURSOR_CONSTANT,
+            seed: None,
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<SimulationConfig, ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        Self::from_toml_str(&contents)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<SimulationConfig, ConfigError> {
+        let config: SimulationConfig = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Fills `materials` with the single default material if none are
+    /// configured, so that every particle's material id is valid.
+    pub fn resolve_materials(&mut self) {
+        if self.materials.is_empty() {
+            self.materials.push(Material::from_config(self));
+        }
+    }
+
+    /// The material with index `id`. Only valid after [`Self::resolve_materials`].
+    pub fn material(&self, id: usize) -> &Material {
+        &self.materials[id]
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        fn invalid(msg: String) -> Result<(), ConfigError> {
+            Err(ConfigError::Invalid(msg))
+        }
+
+        let floats = [
+            ("timestep", self.timestep),
+            ("cfl_factor", self.cfl_factor),
+            ("min_timestep", self.min_timestep),
+            ("max_timestep", self.max_timestep),
+            ("gravity", self.gravity),
+            ("collision_damping", self.collision_damping),
+            ("drag_coefficient", self.drag_coefficient),
+            ("density_floor", self.density_floor),
+            ("pressure_constant", self.pressure_constant),
+            ("near_pressure_constant", self.near_pressure_constant),
+            ("viscosity_constant", self.viscosity_constant),
+            ("surface_tension", self.surface_tension),
+            ("density_error_tolerance", self.density_error_tolerance),
+            ("xsph_viscosity", self.xsph_viscosity),
+            ("cursor_radius", self.cursor_radius),
+            ("cursor_constant", self.cursor_constant),
+        ];
+        for (name, value) in floats {
+            if !value.is_finite() {
+                return invalid(format!("`{}` must be finite, got {}", name, value));
+            }
+        }
+
+        if self.fps == 0 {
+            return invalid("`fps` must be greater than 0".to_string());
+        }
+        if self.timestep <= 0.0 {
+            return invalid(format!(
+                "`timestep` must be greater than 0, got {}",
+                self.timestep
+            ));
+        }
+        if self.adaptive_timestep {
+            if self.cfl_factor <= 0.0 {
+                return invalid(format!(
+                    "`cfl_factor` must be greater than 0, got {}",
+                    self.cfl_factor
+                ));
+            }
+            if self.min_timestep <= 0.0 || self.min_timestep > self.max_timestep {
+                return invalid(format!(
+                    "`min_timestep` must be within 0..=max_timestep, got {} and {}",
+                    self.min_timestep, self.max_timestep
+                ));
+            }
+        }
+        if self.substeps == 0 {
+            return invalid("`substeps` must be greater than 0".to_string());
+        }
+        if self.max_steps_per_frame == 0 {
+            return invalid("`max_steps_per_frame` must be greater than 0".to_string());
+        }
+        if self.window_size.0 == 0 || self.window_size.1 == 0 {
+            return invalid(format!(
+                "`window_size` must be non-zero, got {:?}",
+                self.window_size
+            ));
+        }
+        if self.particle_amt == 0 {
+            return invalid("`particle_amt` must be greater than 0".to_string());
+        }
+        if self.particle_amt > self.max_particles {
+            return invalid(format!(
+                "`particle_amt` {} exceeds `max_particles` {}",
+                self.particle_amt, self.max_particles
+            ));
+        }
+        if self.particle_spacing == 0 {
+            return invalid("`particle_spacing` must be greater than 0".to_string());
+        }
+        if self.smoothing_radius == 0 {
+            return invalid("`smoothing_radius` must be greater than 0".to_string());
+        }
+        if self.particle_radius.saturating_mul(2) >= self.window_size.0.min(self.window_size.1) {
+            return invalid(format!(
+                "`particle_radius` {} does not fit into a {:?} window",
+                self.particle_radius, self.window_size
+            ));
+        }
+        // Even hexagonally packed, a disk takes up `2√3 r²` of the window.
+        let radius = self.particle_radius as f64;
+        let capacity = self.window_size.0 as f64 * self.window_size.1 as f64
+            / (2.0 * 3f64.sqrt() * radius * radius);
+        if self.particle_amt as f64 > capacity {
+            return invalid(format!(
+                "{} particles of radius {} do not fit into a {:?} window",
+                self.particle_amt, self.particle_radius, self.window_size
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.collision_damping) {
+            return invalid(format!(
+                "`collision_damping` must be within 0..=1, got {}",
+                self.collision_damping
+            ));
+        }
+        if let EquationOfState::Tait {
+            stiffness,
+            exponent,
+        } = self.equation_of_state
+        {
+            if !(stiffness.is_finite() && stiffness >= 0.0) {
+                return invalid(format!(
+                    "tait `stiffness` must be finite and not negative, got {}",
+                    stiffness
+                ));
+            }
+            if !(exponent.is_finite() && exponent >= 1.0) {
+                return invalid(format!(
+                    "tait `exponent` must be at least 1, got {}",
+                    exponent
+                ));
+            }
+        }
+        if self.materials.is_empty() && self.density_floor <= 0.0 {
+            return invalid(format!(
+                "`density_floor` is the rest density and must be greater than 0, got {}",
+                self.density_floor
+            ));
+        }
+        if self.solver != SolverType::Explicit {
+            if self.integrator != Integrator::SymplecticEuler {
+                return invalid(format!(
+                    "`integrator` {:?} needs the explicit solver; the iterative solvers only support `symplectic_euler`",
+                    self.integrator
+                ));
+            }
+            if self.max_iterations == 0 {
+                return invalid("`max_iterations` must be greater than 0".to_string());
+            }
+            if self.density_error_tolerance <= 0.0 {
+                return invalid(format!(
+                    "`density_error_tolerance` must be greater than 0, got {}",
+                    self.density_error_tolerance
+                ));
+            }
+        }
+        let walls = &self.walls;
+        for (name, mode) in [
+            ("left", walls.left),
+            ("right", walls.right),
+            ("top", walls.top),
+            ("bottom", walls.bottom),
+        ] {
+            if let WallMode::Solid {
+                restitution,
+                friction,
+            } = mode
+            {
+                if !((0.0..=1.0).contains(&restitution) && (0.0..=1.0).contains(&friction)) {
+                    return invalid(format!(
+                        "`{}` wall `restitution` and `friction` must be within 0..=1, got {} and {}",
+                        name, restitution, friction
+                    ));
+                }
+            }
+        }
+        if (walls.left == WallMode::Periodic) != (walls.right == WallMode::Periodic)
+            || (walls.top == WallMode::Periodic) != (walls.bottom == WallMode::Periodic)
+        {
+            return invalid(
+                "periodic walls must come in pairs, left with right and top with bottom"
+                    .to_string(),
+            );
+        }
+        let finite = |(x, y): (f32, f32)| x.is_finite() && y.is_finite();
+        for obstacle in self.obstacles.iter().chain(&self.drains) {
+            let valid = match obstacle {
+                Obstacle::Circle { center, radius } => {
+                    finite(*center) && radius.is_finite() && *radius > 0.0
+                }
+                Obstacle::Box { center, size } => {
+                    finite(*center) && finite(*size) && size.0 > 0.0 && size.1 > 0.0
+                }
+                Obstacle::Polygon { points } => {
+                    points.len() >= 3 && points.iter().all(|&point| finite(point))
+                }
+            };
+            if !valid {
+                return invalid(format!(
+                    "obstacles and drains need finite coordinates, a positive radius or size and at least 3 polygon points, got {:?}",
+                    obstacle
+                ));
+            }
+        }
+        let material_count = self.materials.len().max(1);
+        for emitter in &self.emitters {
+            let velocity = Vector::from(emitter.velocity);
+            let valid_shape = match emitter.shape {
+                EmitterShape::Point { position } => finite(position) && velocity != Vector::zero(),
+                EmitterShape::Line { start, end } => finite(start) && finite(end),
+                EmitterShape::Nozzle { position, width } => {
+                    finite(position)
+                        && width.is_finite()
+                        && width > 0.0
+                        && velocity != Vector::zero()
+                }
+            };
+            if !(valid_shape
+                && finite(emitter.velocity)
+                && emitter.rate.is_finite()
+                && emitter.rate >= 0.0
+                && emitter.material < material_count)
+            {
+                return invalid(format!(
+                    "emitters need finite coordinates, a rate of at least 0, an existing material, a positive nozzle width and a velocity for points and nozzles, got {:?}",
+                    emitter
+                ));
+            }
+        }
+        for body in &self.rigid_bodies {
+            let valid_shape = match &body.shape {
+                BodyShape::Circle { radius } => radius.is_finite() && *radius > 0.0,
+                BodyShape::Polygon { points } => {
+                    points.len() >= 3
+                        && points.iter().all(|&point| finite(point))
+                        && rigid_body::is_convex(points)
+                }
+            };
+            if !(valid_shape
+                && finite(body.position)
+                && finite(body.velocity)
+                && body.angle.is_finite()
+                && body.angular_velocity.is_finite()
+                && body.density.is_finite()
+                && body.density > 0.0)
+            {
+                return invalid(format!(
+                    "rigid bodies need finite coordinates, a positive radius or a convex polygon and a positive density, got {:?}",
+                    body
+                ));
+            }
+        }
+        for material in &self.materials {
+            let name = &material.name;
+            for (field, value) in [
+                ("mass", material.mass),
+                ("rest_density", material.rest_density),
+            ] {
+                if !(value.is_finite() && value > 0.0) {
+                    return invalid(format!(
+                        "material `{}` `{}` must be finite and greater than 0, got {}",
+                        name, field, value
+                    ));
+                }
+            }
+            for (field, value) in [
+                ("viscosity", material.viscosity),
+                ("stiffness", material.stiffness),
+                ("fraction", material.fraction),
+            ] {
+                if !(value.is_finite() && value >= 0.0) {
+                    return invalid(format!(
+                        "material `{}` `{}` must be finite and not negative, got {}",
+                        name, field, value
+                    ));
+                }
+            }
+        }
+        if !self.materials.is_empty()
+            && self
+                .materials
+                .iter()
+                .all(|material| material.fraction == 0.0)
+        {
+            return invalid("at least one material needs a `fraction` above 0".to_string());
+        }
+        for (name, value) in [
+            ("drag_coefficient", self.drag_coefficient),
+            ("near_pressure_constant", self.near_pressure_constant),
+            ("viscosity_constant", self.viscosity_constant),
+            ("surface_tension", self.surface_tension),
+            ("xsph_viscosity", self.xsph_viscosity),
+            ("cursor_radius", self.cursor_radius),
+        ] {
+            if value < 0.0 {
+                return invalid(format!("`{}` must not be negative, got {}", name, value));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_invalid(toml: &str) {
+        match SimulationConfig::from_toml_str(toml) {
+            Err(ConfigError::Invalid(_)) => {}
+            other => panic!("expected {:?} to be rejected, got {:?}", toml, other),
+        }
+    }
+
+    #[test]
+    fn default_and_example_configs_are_valid() {
+        SimulationConfig::default().validate().unwrap();
+        SimulationConfig::from_toml_str(include_str!("../simulation.example.toml")).unwrap();
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        for toml in [
+            "densty_floor = 20.0",
+            "[walls.left]\ntype = \"solid\"\nbounce = 1.0",
+            "[[materials]]\nname = \"a\"\nmass = 1.0\nrest_density = 1.0\nviscosity = 0.0\nstiffness = 0.0\ncolor = [0, 0, 0]\nheat = 1.0",
+        ] {
+            assert!(
+                matches!(
+                    SimulationConfig::from_toml_str(toml),
+                    Err(ConfigError::Parse(_))
+                ),
+                "{:?}",
+                toml
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        for toml in [
+            "timestep = nan",
+            "fps = 0",
+            "timestep = 0.0",
+            "adaptive_timestep = true\ncfl_factor = 0.0",
+            "adaptive_timestep = true\nmin_timestep = 0.1\nmax_timestep = 0.01",
+            "substeps = 0",
+            "max_steps_per_frame = 0",
+            "window_size = [0, 900]",
+            "particle_amt = 0",
+            "particle_amt = 100\nmax_particles = 99",
+            "particle_spacing = 0",
+            "smoothing_radius = 0",
+            "particle_radius = 450",
+            "particle_radius = 4294967295",
+            "window_size = [100, 100]\nparticle_amt = 1000\nparticle_radius = 2",
+            "collision_damping = 1.5",
+            "[equation_of_state]\ntype = \"tait\"\nstiffness = -1.0\nexponent = 7.0",
+            "[equation_of_state]\ntype = \"tait\"\nstiffness = 1000.0\nexponent = 0.5",
+            "density_floor = 0.0",
+            "density_floor = 0.0\n[equation_of_state]\ntype = \"tait\"\nstiffness = 1000.0\nexponent = 7.0",
+            "solver = \"pbf\"\nintegrator = \"rk2\"",
+            "solver = \"pbf\"\nmax_iterations = 0",
+            "solver = \"pbf\"\ndensity_error_tolerance = 0.0",
+            "[walls.left]\ntype = \"solid\"\nfriction = 2.0",
+            "[walls.left]\ntype = \"periodic\"",
+            "[[obstacles]]\ntype = \"circle\"\ncenter = [0.0, 0.0]\nradius = 0.0",
+            "[[drains]]\ntype = \"polygon\"\npoints = [[0.0, 0.0], [1.0, 0.0]]",
+            "[[emitters]]\nshape = { type = \"point\", position = [0.0, 0.0] }\nrate = 1.0",
+            "[[emitters]]\nshape = { type = \"line\", start = [0.0, 0.0], end = [10.0, 0.0] }\nrate = -1.0",
+            "[[emitters]]\nshape = { type = \"line\", start = [0.0, 0.0], end = [10.0, 0.0] }\nrate = 1.0\nmaterial = 1",
+            "[[rigid_bodies]]\nshape = { type = \"circle\", radius = 10.0 }\nposition = [0.0, 0.0]\ndensity = 0.0",
+            "[[rigid_bodies]]\nshape = { type = \"polygon\", points = [[0.0, 0.0], [10.0, 10.0], [10.0, 0.0], [0.0, 10.0]] }\nposition = [0.0, 0.0]\ndensity = 1.0",
+            "[[materials]]\nname = \"a\"\nmass = 0.0\nrest_density = 1.0\nviscosity = 0.0\nstiffness = 0.0\ncolor = [0, 0, 0]",
+            "[[materials]]\nname = \"a\"\nmass = 1.0\nrest_density = 1.0\nviscosity = -1.0\nstiffness = 0.0\ncolor = [0, 0, 0]",
+            "[[materials]]\nname = \"a\"\nmass = 1.0\nrest_density = 1.0\nviscosity = 0.0\nstiffness = 0.0\ncolor = [0, 0, 0]\nfraction = 0.0",
+            "drag_coefficient = -0.1",
+        ] {
+            assert_invalid(toml);
+        }
+    }
+}
@@ -1,4 +1,11 @@
 pub const FPS: u64 = 60;
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+pub const SUBSTEPS: u32 = 1;
+pub const MAX_STEPS_PER_FRAME: u32 = 4;
+pub const THREADS: usize = 0;
+pub const CFL_FACTOR: f32 = 0.4;
+pub const MIN_TIMESTEP: f32 = 0.0005;
+pub const MAX_TIMESTEP: f32 = 1.0 / 60.0;
 pub const WINDOW_SIZE: (u32, u32) = (1200, 900);
 
 pub const GRAVITY: f32 = 150.0;
@@ -6,13 +13,21 @@ pub const COLLISION_DAMPING: f32 = 1.0;
 pub const DRAG_COEFFICIENT: f32 = 0.01;
 
 pub const PARTICLE_AMT: usize = 2048;
+pub const MAX_PARTICLES: usize = 16384;
 pub const PARTICLE_RADIUS: u32 = 3;
 pub const PARTICLE_SPACING: u32 = 6;
+pub const PARTICLE_COLOR: (u8, u8, u8) = (0, 0, 255);
 
 pub const SMOOTHING_RADIUS: u32 = 20;
-pub const DENSITY_FLOOR: f32 = 20.0;
-pub const PRESSURE_CONSTANT: f32 = 800.0;
-pub const VISCOSITY_CONSTANT: f32 = 1000.0;
+pub const DENSITY_FLOOR: f32 = 0.028;
+pub const PRESSURE_CONSTANT: f32 = 10_000_000.0;
+pub const NEAR_PRESSURE_CONSTANT: f32 = 500_000.0;
+pub const VISCOSITY_CONSTANT: f32 = 500.0;
+pub const SURFACE_TENSION: f32 = 0.0;
+
+pub const MAX_ITERATIONS: u32 = 50;
+pub const DENSITY_ERROR_TOLERANCE: f32 = 0.01;
+pub const XSPH_VISCOSITY: f32 = 0.01;
 
 pub const CURSOR_RADIUS: f32 = 128.0;
 pub const CURSOR_CONSTANT: f32 = 5.0;
@@ -0,0 +1,135 @@
+use super::{
+    kernel::Kernels,
+    particles_lookup::{LookupPosition, ParticlesLookup},
+    solver::kernel_gradient,
+    vector::Vector,
+    walls::Walls,
+};
+
+/// A sample on the surface of a solid that takes part in the SPH sums
+/// (Akinci et al. 2012).
+///
+/// Fluid particles count it towards their density and are pushed off it by
+/// their own pressure, mirrored onto the sample. The reaction to that push is
+/// the force the fluid exerts on the solid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundaryParticle {
+    pub position: Vector,
+    pub velocity: Vector,
+    /// Share of the surface the sample stands for, counted in fluid particles
+    /// packed at `particle_spacing`: `1 / (s² Σ_k W_bk)` over the samples of
+    /// the same surface.
+    pub volume: f32,
+    /// Index of the rigid body the sample belongs to, `None` for the walls.
+    pub body: Option<usize>,
+}
+
+impl LookupPosition for BoundaryParticle {
+    fn lookup_position(&self) -> Vector {
+        self.position
+    }
+
+    fn translate(&mut self, offset: Vector) {
+        self.position += offset;
+    }
+}
+
+/// Grid of every boundary sample in the scene, rebuilt each step.
+pub type BoundaryLookup = ParticlesLookup<BoundaryParticle>;
+
+/// Samples along the solid window edges, continued `reach` past the corners so
+/// particles there see a full neighbourhood of wall.
+pub fn wall_particles(
+    window_size: (u32, u32),
+    walls: &Walls,
+    spacing: f32,
+    reach: f32,
+    kernels: &Kernels,
+) -> Vec<BoundaryParticle> {
+    let size = Vector::from(window_size);
+    let count = (size / spacing).ceil();
+    let step = Vector::new(size.x / count.x, size.y / count.y);
+    let (count_x, count_y) = (count.x as i32, count.y as i32);
+    let overhang = (reach / spacing).ceil() as i32;
+
+    let mut points = Vec::new();
+    for k in -overhang..=count_x + overhang {
+        let x = k as f32 * step.x;
+        // The corners stand in for the side walls too.
+        let corner = (k == 0 && walls.left.is_solid()) || (k == count_x && walls.right.is_solid());
+        if walls.top.is_solid() || corner {
+            points.push(Vector::new(x, 0.0));
+        }
+        if walls.bottom.is_solid() || corner {
+            points.push(Vector::new(x, size.y));
+        }
+    }
+    for k in (-overhang..=count_y + overhang).filter(|&k| k != 0 && k != count_y) {
+        let y = k as f32 * step.y;
+        if walls.left.is_solid() {
+            points.push(Vector::new(0.0, y));
+        }
+        if walls.right.is_solid() {
+            points.push(Vector::new(size.x, y));
+        }
+    }
+
+    // Samples past a periodic seam only count towards the volumes; the
+    // lookup's ghosts take their place.
+    let (period_x, period_y) = walls.period(window_size);
+    let within = |coordinate: f32, period: Option<f32>| {
+        period.is_none_or(|length| (0.0..length).contains(&coordinate))
+    };
+    let volumes = sample_volumes(&points, spacing, kernels);
+    points
+        .into_iter()
+        .zip(volumes)
+        .filter(|&(position, _)| within(position.x, period_x) && within(position.y, period_y))
+        .map(|(position, volume)| BoundaryParticle {
+            position,
+            velocity: Vector::zero(),
+            volume,
+            body: None,
+        })
+        .collect()
+}
+
+/// `volume` of each of `points`, which together sample one surface.
+pub fn sample_volumes(points: &[Vector], spacing: f32, kernels: &Kernels) -> Vec<f32> {
+    points
+        .iter()
+        .map(|&point| {
+            let number_density: f32 = points
+                .iter()
+                .map(|&other| kernels.density.value((point - other).magnitude()))
+                .sum();
+            1.0 / (spacing * spacing * number_density)
+        })
+        .collect()
+}
+
+/// `Σ_b V_b ∇W(x - x_b)`: scaled by the particle's pressure term, the push of
+/// the boundary on a particle at `point`.
+pub fn boundary_gradient(boundary: &BoundaryLookup, point: Vector, kernels: &Kernels) -> Vector {
+    let mut gradient = Vector::zero();
+    for sample in boundary.query_around(point).particles() {
+        gradient += kernel_gradient(kernels, point - sample.position) * sample.volume;
+    }
+    gradient
+}
+
+/// `Σ_b V_b (v - v_b) · ∇W(x - x_b)`, the boundary's share of the number
+/// density change rate of a particle at `point` moving with `velocity`.
+pub fn boundary_divergence(
+    boundary: &BoundaryLookup,
+    point: Vector,
+    velocity: Vector,
+    kernels: &Kernels,
+) -> f32 {
+    let mut divergence = 0.0;
+    for sample in boundary.query_around(point).particles() {
+        let gradient = kernel_gradient(kernels, point - sample.position);
+        divergence += (velocity - sample.velocity).dot(gradient) * sample.volume;
+    }
+    divergence
+}
@@ -0,0 +1,241 @@
+use crate::config::SimulationConfig;
+
+use super::{
+    boundary::{boundary_divergence, boundary_gradient},
+    executor::Executor,
+    kernel::Kernels,
+    particle::Particle,
+    particles_lookup::ParticlesLookup,
+    solver::{
+        kernel_gradient, non_pressure_accelerations, DensityPass, Solver, SolverStats, SolverStep,
+    },
+    vector::Vector,
+};
+
+/// Divergence-free SPH (Bender & Koschier 2015).
+///
+/// Two pressure solves correct the velocities directly: one keeps the density
+/// at the material's rest density after the step, the other drives the velocity divergence
+/// to zero. Both stop at `density_error_tolerance` or `max_iterations`.
+#[derive(Debug, Default)]
+pub struct Dfsph {
+    density_pass: DensityPass,
+    non_pressure: Vec<Vector>,
+    velocities: Vec<Vector>,
+    corrected: Vec<Vector>,
+    factors: Vec<f32>,
+    stiffness: Vec<f32>,
+    stiffness_sums: Vec<f32>,
+    sources: Vec<f32>,
+    /// `Σ_b V_b ∇W_ib` and `-Σ_b V_b v_b · ∇W_ib` of every particle; positions
+    /// do not change during the solves.
+    boundary_terms: Vec<(Vector, f32)>,
+}
+
+impl Dfsph {
+    /// Iterations the density solve runs even if the error is already small.
+    pub const MIN_DENSITY_ITERATIONS: u32 = 2;
+    pub const MIN_DIVERGENCE_ITERATIONS: u32 = 1;
+    /// Share of the Jacobi stiffness applied per iteration. At full strength
+    /// the corrections of neighbouring particles add up, and a resting tank
+    /// keeps jittering below rest density.
+    pub const RELAXATION: f32 = 0.5;
+
+    pub fn new() -> Dfsph {
+        Dfsph::default()
+    }
+
+    /// `α_i = ρ_i / (|Σ m_i ∇W_ij|² + Σ m_i / m_j |m_i ∇W_ij|²)`, the factor
+    /// that turns a density change into a stiffness. Boundary samples only
+    /// enter the first sum as they do not move. Zero for particles without
+    /// neighbours.
+    fn factor(
+        lookup: &ParticlesLookup,
+        index: usize,
+        boundary_gradient: Vector,
+        kernels: &Kernels,
+    ) -> f32 {
+        let particle = &lookup.particles[index];
+        let mut gradient_sum = Vector::zero();
+        let mut gradient_dot_sum = 0.0;
+        for other in lookup.query_neighbors(index).particles() {
+            let gradient =
+                kernel_gradient(kernels, particle.position - other.position) * particle.mass;
+            gradient_sum += gradient;
+            gradient_dot_sum += gradient.dot(gradient) * (particle.mass / other.mass);
+        }
+        gradient_sum += boundary_gradient * particle.mass;
+        let denominator = gradient_sum.dot(gradient_sum) + gradient_dot_sum;
+        if denominator > 1e-12 {
+            particle.density / denominator
+        } else {
+            0.0
+        }
+    }
+
+    /// Jacobi solve on `self.velocities`. With `divergence` the source term is
+    /// the density change rate `Dρ/Dt`, otherwise the density predicted after
+    /// moving with the current velocities. Adds every stiffness applied to
+    /// `self.stiffness_sums`. Returns the iterations taken.
+    fn solve(
+        &mut self,
+        lookup: &ParticlesLookup,
+        executor: &Executor,
+        config: &SimulationConfig,
+        kernels: &Kernels,
+        delta_time: f32,
+        divergence: bool,
+    ) -> u32 {
+        let particle_amt = lookup.particles.len();
+        let min_iterations = if divergence {
+            Self::MIN_DIVERGENCE_ITERATIONS
+        } else {
+            Self::MIN_DENSITY_ITERATIONS
+        };
+
+        let mut iterations = 0;
+        let boundary_terms = &self.boundary_terms;
+        while iterations < config.max_iterations {
+            let velocities = &self.velocities;
+            executor.map_into(&mut self.sources, particle_amt, |i| {
+                let particle = &lookup.particles[i];
+                let rest_density = particle.rest_density(config);
+                let mut rate = 0.0;
+                for (j, other) in lookup.query_neighbors(i) {
+                    let gradient = kernel_gradient(kernels, particle.position - other.position);
+                    rate += particle.mass * (velocities[i] - velocities[j]).dot(gradient);
+                }
+                let (boundary_gradient, boundary_rate) = boundary_terms[i];
+                rate += particle.mass * (velocities[i].dot(boundary_gradient) + boundary_rate);
+                // Only compression is corrected; expanding regions are free surface.
+                if divergence {
+                    rate.max(0.0) * delta_time + rest_density
+                } else {
+                    (particle.density + rate * delta_time).max(rest_density)
+                }
+            });
+
+            iterations += 1;
+            let error = Self::average_error(&self.sources, lookup, config);
+            if iterations > min_iterations && error <= config.density_error_tolerance {
+                break;
+            }
+
+            let sources = &self.sources;
+            let factors = &self.factors;
+            executor.map_into(&mut self.stiffness, particle_amt, |i| {
+                let rest_density = lookup.particles[i].rest_density(config);
+                (sources[i] - rest_density) / (delta_time * delta_time)
+                    * factors[i]
+                    * Self::RELAXATION
+            });
+            let stiffness = &self.stiffness;
+            executor.for_each_mut(&mut self.stiffness_sums, |i, sum| *sum += stiffness[i]);
+
+            let stiffness = &self.stiffness;
+            let velocities = &self.velocities;
+            executor.map_into(&mut self.corrected, particle_amt, |i| {
+                let particle = &lookup.particles[i];
+                let own = Self::pressure_term(particle, stiffness[i], config);
+                let mut correction = Vector::zero();
+                for (j, other) in lookup.query_neighbors(i) {
+                    let gradient = kernel_gradient(kernels, particle.position - other.position);
+                    let shared = own + Self::pressure_term(other, stiffness[j], config);
+                    correction -= gradient * (shared / particle.mass) * delta_time;
+                }
+                let (boundary_gradient, _) = boundary_terms[i];
+                correction -= boundary_gradient * (own / particle.mass) * delta_time;
+                velocities[i] + correction
+            });
+            std::mem::swap(&mut self.velocities, &mut self.corrected);
+        }
+        iterations
+    }
+
+    /// `κ m² / ρ`, one side of the symmetric pressure force `-Σ_j (κ_i m_i² / ρ_i
+    /// + κ_j m_j² / ρ_j) ∇W_ij`, with the density floored at rest density.
+    fn pressure_term(particle: &Particle, stiffness: f32, config: &SimulationConfig) -> f32 {
+        stiffness / particle.density.max(particle.rest_density(config))
+            * particle.mass
+            * particle.mass
+    }
+
+    fn average_error(sources: &[f32], lookup: &ParticlesLookup, config: &SimulationConfig) -> f32 {
+        SolverStats::average_density_error(sources.iter().copied(), &lookup.particles, config)
+    }
+}
+
+impl Solver for Dfsph {
+    fn name(&self) -> &'static str {
+        "dfsph"
+    }
+
+    fn step(&mut self, mut step: SolverStep<'_>) -> SolverStats {
+        // DFSPH works on the current positions, not the explicit prediction.
+        step.executor
+            .for_each_mut(&mut step.lookup.particles, |_, particle| {
+                particle.predicted_position = particle.position
+            });
+        self.density_pass.evaluate(&mut step);
+        let SolverStep {
+            lookup,
+            boundary,
+            executor,
+            config,
+            kernels,
+            cursor,
+            delta_time,
+            step_seed,
+        } = step;
+        let particle_amt = lookup.particles.len();
+
+        let snapshot = &*lookup;
+        executor.map_into(&mut self.boundary_terms, particle_amt, |i| {
+            let point = snapshot.particles[i].position;
+            (
+                boundary_gradient(boundary, point, kernels),
+                boundary_divergence(boundary, point, Vector::zero(), kernels),
+            )
+        });
+        let boundary_terms = &self.boundary_terms;
+        executor.map_into(&mut self.factors, particle_amt, |i| {
+            Self::factor(snapshot, i, boundary_terms[i].0, kernels)
+        });
+        self.stiffness_sums.clear();
+        self.stiffness_sums.resize(particle_amt, 0.0);
+        self.velocities.clear();
+        self.velocities
+            .extend(lookup.particles.iter().map(|particle| particle.velocity));
+
+        let divergence_iterations = self.solve(lookup, executor, config, kernels, delta_time, true);
+
+        non_pressure_accelerations(
+            &mut self.non_pressure,
+            lookup,
+            executor,
+            config,
+            kernels,
+            cursor,
+            step_seed,
+        );
+        for (velocity, acceleration) in self.velocities.iter_mut().zip(&self.non_pressure) {
+            *velocity += *acceleration * delta_time;
+        }
+
+        let density_iterations = self.solve(lookup, executor, config, kernels, delta_time, false);
+        let density_error = Self::average_error(&self.sources, lookup, config);
+
+        let velocities = &self.velocities;
+        let stiffness_sums = &self.stiffness_sums;
+        executor.for_each_mut(&mut lookup.particles, |i, particle| {
+            particle.boundary_pressure = Self::pressure_term(particle, stiffness_sums[i], config);
+            let acceleration = (velocities[i] - particle.velocity) / delta_time;
+            particle.integrate(acceleration, delta_time, config)
+        });
+
+        SolverStats {
+            iterations: divergence_iterations + density_iterations,
+            density_error,
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use serde::Deserialize;
+
+use crate::config::SimulationConfig;
+
+use super::{particle::Particle, vector::Vector};
+
+/// Where an [`Emitter`] places its particles.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum EmitterShape {
+    /// Every particle starts at `position`.
+    Point { position: (f32, f32) },
+    /// Particles start at points `particle_spacing` apart from `start` to
+    /// `end`, one after the other.
+    Line { start: (f32, f32), end: (f32, f32) },
+    /// A line of length `width` centred on `position` and across the emitter
+    /// velocity, so the particles leave it as a jet.
+    Nozzle { position: (f32, f32), width: f32 },
+}
+
+/// A particle source, as an `[[emitters]]` table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmitterConfig {
+    pub shape: EmitterShape,
+    /// Initial velocity of the emitted particles.
+    #[serde(default)]
+    pub velocity: (f32, f32),
+    /// Particles per second.
+    pub rate: f32,
+    /// Index into `SimulationConfig::materials`.
+    #[serde(default)]
+    pub material: usize,
+}
+
+/// A running emitter: its spawn points and how much of the next particle is
+/// already due.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Emitter {
+    /// Spawn points, used in turn.
+    pub slots: Vec<Vector>,
+    pub velocity: Vector,
+    pub rate: f32,
+    pub material: usize,
+    pending: f32,
+    next_slot: usize,
+}
+
+impl Emitter {
+    /// Builds the emitter described by `config`, with line and nozzle slots
+    /// `spacing` apart.
+    pub fn new(config: &EmitterConfig, spacing: f32) -> Emitter {
+        let velocity = Vector::from(config.velocity);
+        let slots = match config.shape {
+            EmitterShape::Point { position } => vec![Vector::from(position)],
+            EmitterShape::Line { start, end } => line_slots(start.into(), end.into(), spacing),
+            EmitterShape::Nozzle { position, width } => {
+                let center = Vector::from(position);
+                let across = Vector::new(-velocity.y, velocity.x).normalize() * (width / 2.0);
+                line_slots(center - across, center + across, spacing)
+            }
+        };
+        Emitter {
+            slots,
+            velocity,
+            rate: config.rate,
+            material: config.material,
+            pending: 0.0,
+            next_slot: 0,
+        }
+    }
+
+    /// Appends the particles that became due during the last `delta_time` to
+    /// `particles`. Each one has already travelled for the time since it was
+    /// due, so particles leaving the same slot in one step do not overlap.
+    /// Particles due while `max_particles` is reached are dropped.
+    pub fn emit(
+        &mut self,
+        delta_time: f32,
+        config: &SimulationConfig,
+        particles: &mut Vec<Particle>,
+    ) {
+        self.pending += self.rate * delta_time;
+        while self.pending >= 1.0 {
+            self.pending -= 1.0;
+            if particles.len() >= config.max_particles {
+                continue;
+            }
+            let age = self.pending / self.rate;
+            let position = self.slots[self.next_slot] + self.velocity * age;
+            self.next_slot = (self.next_slot + 1) % self.slots.len();
+            particles.push(Particle::spawn(
+                position,
+                self.velocity,
+                self.material,
+                config,
+            ));
+        }
+    }
+}
+
+/// Points at most `spacing` apart from `start` to `end`, both included.
+fn line_slots(start: Vector, end: Vector, spacing: f32) -> Vec<Vector> {
+    let gaps = ((end - start).magnitude() / spacing).ceil();
+    if gaps == 0.0 {
+        return vec![start];
+    }
+    let step = (end - start) / gaps;
+    (0..=gaps as usize)
+        .map(|k| start + step * k as f32)
+        .collect()
+}
@@ -0,0 +1,37 @@
+use serde::Deserialize;
+
+use crate::config::SimulationConfig;
+
+use super::material::Material;
+
+/// Maps a particle's density to its pressure.
+///
+/// Both laws use the particle material's `rest_density`, and negative results
+/// are clamped to zero when `clamp_negative_pressure` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum EquationOfState {
+    /// `p = k (ρ - ρ₀)` with the material's `stiffness` as `k`.
+    Linear,
+    /// Tait equation from weakly-compressible SPH, `p = B ((ρ / ρ₀)^γ - 1)`.
+    /// `exponent` is 7 for water; higher `stiffness` keeps density variation smaller.
+    Tait { stiffness: f32, exponent: f32 },
+}
+
+impl EquationOfState {
+    pub fn pressure(&self, density: f32, material: &Material, config: &SimulationConfig) -> f32 {
+        let rest_density = material.rest_density;
+        let pressure = match *self {
+            EquationOfState::Linear => material.stiffness * (density - rest_density),
+            EquationOfState::Tait {
+                stiffness,
+                exponent,
+            } => stiffness * ((density / rest_density).powf(exponent) - 1.0),
+        };
+        if config.clamp_negative_pressure {
+            pressure.max(0.0)
+        } else {
+            pressure
+        }
+    }
+}
@@ -0,0 +1,70 @@
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Runs the per-particle passes of a step, either serially or on a rayon
+/// thread pool when the `parallel` feature is enabled.
+///
+/// Every pass writes each result to its own slot, so the output is identical
+/// no matter how many threads are used.
+pub struct Executor {
+    #[cfg(feature = "parallel")]
+    pool: rayon::ThreadPool,
+}
+
+impl Executor {
+    /// `threads == 0` uses one thread per core. Ignored without the `parallel` feature.
+    #[cfg(feature = "parallel")]
+    pub fn new(threads: usize) -> Executor {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .expect("failed to build simulation thread pool");
+        Executor { pool }
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    pub fn new(_threads: usize) -> Executor {
+        Executor {}
+    }
+
+    pub fn threads(&self) -> usize {
+        #[cfg(feature = "parallel")]
+        return self.pool.current_num_threads();
+        #[cfg(not(feature = "parallel"))]
+        return 1;
+    }
+
+    /// Replaces the contents of `out` with `f(0), f(1), .., f(len - 1)`.
+    pub fn map_into<T, F>(&self, out: &mut Vec<T>, len: usize, f: F)
+    where
+        T: Send,
+        F: Fn(usize) -> T + Sync + Send,
+    {
+        out.clear();
+        #[cfg(feature = "parallel")]
+        self.pool
+            .install(|| out.par_extend((0..len).into_par_iter().map(f)));
+        #[cfg(not(feature = "parallel"))]
+        out.extend((0..len).map(f));
+    }
+
+    /// Calls `f` with the index of and a mutable reference to every item.
+    pub fn for_each_mut<T, F>(&self, items: &mut [T], f: F)
+    where
+        T: Send,
+        F: Fn(usize, &mut T) + Sync + Send,
+    {
+        #[cfg(feature = "parallel")]
+        self.pool.install(|| {
+            items
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, item)| f(i, item))
+        });
+        #[cfg(not(feature = "parallel"))]
+        items
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, item)| f(i, item));
+    }
+}
@@ -0,0 +1,75 @@
+use super::{
+    solver::{DensityPass, Solver, SolverStats, SolverStep},
+    utils::particle_rng,
+    vector::Vector,
+};
+
+/// The original solver: one pass of the configured force model per step.
+///
+/// Densities are taken at the predicted positions, every particle's acceleration
+/// is computed from them, then all particles are integrated. Each stage only
+/// reads results of the previous one, so the outcome does not depend on
+/// particle order.
+#[derive(Debug, Default)]
+pub struct Explicit {
+    density_pass: DensityPass,
+    accelerations: Vec<Vector>,
+}
+
+impl Explicit {
+    pub fn new() -> Explicit {
+        Explicit::default()
+    }
+}
+
+impl Solver for Explicit {
+    fn name(&self) -> &'static str {
+        "explicit"
+    }
+
+    fn step(&mut self, mut step: SolverStep<'_>) -> SolverStats {
+        self.density_pass.run(&mut step);
+        let SolverStep {
+            lookup,
+            boundary,
+            executor,
+            config,
+            kernels,
+            cursor,
+            delta_time,
+            step_seed,
+        } = step;
+
+        let snapshot = &*lookup;
+        executor.map_into(&mut self.accelerations, snapshot.particles.len(), |i| {
+            let mut rng = particle_rng(step_seed, i);
+            let current = &snapshot.particles[i];
+            let other_particles = snapshot.query_neighbors(i).particles();
+            let boundary_samples = boundary
+                .query_around(current.predicted_position)
+                .particles();
+            current.calculate_acceleration(
+                other_particles,
+                boundary_samples,
+                cursor,
+                config,
+                kernels,
+                &mut rng,
+            )
+        });
+        let accelerations = &self.accelerations;
+        executor.for_each_mut(&mut lookup.particles, |i, particle| {
+            particle.boundary_pressure = particle.explicit_boundary_pressure(config);
+            particle.integrate(accelerations[i], delta_time, config)
+        });
+
+        SolverStats {
+            iterations: 0,
+            density_error: SolverStats::average_density_error(
+                self.density_pass.densities(),
+                &lookup.particles,
+                config,
+            ),
+        }
+    }
+}
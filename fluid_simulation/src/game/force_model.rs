@@ -0,0 +1,13 @@
+use serde::Deserialize;
+
+/// How neighbouring particles push each other apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForceModel {
+    /// Single pressure term from the configured equation of state.
+    Pressure,
+    /// Clavet et al. double density relaxation: the pressure term plus a
+    /// near-pressure term from a sharper kernel that only ever repels, which
+    /// prevents clustering and gives the free surface its tension.
+    DoubleDensity,
+}
@@ -1,13 +1,22 @@
-use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-use crate::constants::{self};
+use crate::config::SimulationConfig;
 use crate::game::utils::calculate_density;
 
 use crate::game::particle::Particle;
 
+use super::boundary::{wall_particles, BoundaryLookup, BoundaryParticle};
 use super::cursor::Cursor;
-use super::particle;
+use super::emitter::Emitter;
+use super::executor::Executor;
+use super::integrator::Integrator;
+use super::kernel::Kernels;
+use super::material::Material;
 use super::particles_lookup::ParticlesLookup;
+use super::rigid_body::RigidBody;
+use super::solver::{kernel_gradient, Solver, SolverStats, SolverStep, SolverType};
 use super::vector::Vector;
 
 #[derive(PartialEq)]
@@ -18,24 +27,57 @@ pub enum GameState {
 
 pub struct GameContext {
     pub state: GameState,
+    pub config: SimulationConfig,
+    /// Whether `config.materials` holds the single material derived by
+    /// `SimulationConfig::resolve_materials`, whose rest density follows the
+    /// solver.
+    derived_material: bool,
+    pub seed: u64,
+    rng: StdRng,
     pub heatmap: Vec<Vec<f32>>,
     pub heatmap_resolution: u32,
     pub particles_lookup: ParticlesLookup,
+    pub rigid_bodies: Vec<RigidBody>,
+    pub emitters: Vec<Emitter>,
+    /// Wall samples and the outline samples of the rigid bodies, refreshed
+    /// before every solver step.
+    pub boundary: BoundaryLookup,
+    /// Samples along the window edges, built once.
+    walls: Vec<BoundaryParticle>,
+    pub kernels: Kernels,
+    pub solver_stats: SolverStats,
+    /// Length of the most recent step in seconds.
+    pub delta_time: f32,
+    executor: Executor,
+    solver: Box<dyn Solver>,
+    start_states: Vec<(Vector, Vector)>,
+    midpoint_velocities: Vec<Vector>,
+    body_contacts: Vec<usize>,
+    boundary_forces: Vec<Vector>,
 }
 
 impl GameContext {
-    pub fn new(use_random_pos: bool, heatmap_resolution: u32) -> GameContext {
-        let heatmap_width = (constants::WINDOW_SIZE.0 / heatmap_resolution + 1) as usize;
-        let heatmap_height = (constants::WINDOW_SIZE.1 / heatmap_resolution + 1) as usize;
+    /// Random positions tried for a particle before it is placed on top of
+    /// a neighbour, which the pressure then pushes apart.
+    const MAX_PLACEMENT_ATTEMPTS: u32 = 1000;
 
-        let particles = if use_random_pos {
-            Self::create_particles_random_pos()
-        } else {
-            Self::create_particles_grid()
-        };
+    pub fn new(
+        mut config: SimulationConfig,
+        use_random_pos: bool,
+        heatmap_resolution: u32,
+    ) -> GameContext {
+        let heatmap_width = (config.window_size.0 / heatmap_resolution + 1) as usize;
+        let heatmap_height = (config.window_size.1 / heatmap_resolution + 1) as usize;
+
+        let derived_material = config.materials.is_empty();
+        config.resolve_materials();
+        let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let mut rng = StdRng::seed_from_u64(seed);
 
-        let particles_lookup_size = Particle::SMOOTHING_RADIUS as f32;
-        let particles_lookup_dimensions: (usize, usize) = (Vector::from(constants::WINDOW_SIZE)
+        let particles = Self::create_particles(&config, use_random_pos, &mut rng);
+
+        let particles_lookup_size = config.smoothing_radius as f32;
+        let particles_lookup_dimensions: (usize, usize) = (Vector::from(config.window_size)
             / particles_lookup_size)
             .ceil()
             .try_into()
@@ -45,26 +87,117 @@ impl GameContext {
             particles_lookup_size,
             particles_lookup_dimensions,
         );
+        let period = config.walls.period(config.window_size);
+        particles_lookup.set_period(period);
         particles_lookup.update_cells();
+        let mut boundary = BoundaryLookup::new(
+            Vec::new(),
+            particles_lookup_size,
+            particles_lookup_dimensions,
+        );
+        boundary.set_period(period);
+
+        let kernels = Kernels::new(
+            config.kernel,
+            config.viscosity_kernel,
+            config.smoothing_radius as f32,
+        );
+        let rigid_bodies = Self::create_rigid_bodies(&config, &kernels);
+        let emitters = Self::create_emitters(&config);
+        let walls = wall_particles(
+            config.window_size,
+            &config.walls,
+            config.particle_spacing as f32,
+            kernels.density.support_radius(),
+            &kernels,
+        );
+        let executor = Executor::new(config.threads);
+        let solver = config.solver.build();
 
-        GameContext {
+        let mut context = GameContext {
             state: GameState::Paused,
+            config,
+            derived_material,
+            seed,
+            rng,
             heatmap: vec![vec![0.0; heatmap_height]; heatmap_width],
-            heatmap_resolution: heatmap_resolution,
-            particles_lookup: particles_lookup,
+            heatmap_resolution,
+            particles_lookup,
+            rigid_bodies,
+            emitters,
+            boundary,
+            walls,
+            kernels,
+            solver_stats: SolverStats::default(),
+            delta_time: 0.0,
+            executor,
+            solver,
+            start_states: Vec::new(),
+            midpoint_velocities: Vec::new(),
+            body_contacts: Vec::new(),
+            boundary_forces: Vec::new(),
+        };
+        context.update_boundary();
+        context
+    }
+
+    fn create_rigid_bodies(config: &SimulationConfig, kernels: &Kernels) -> Vec<RigidBody> {
+        let spacing = config.particle_spacing as f32;
+        config
+            .rigid_bodies
+            .iter()
+            .map(|body| RigidBody::new(body, spacing, kernels))
+            .collect()
+    }
+
+    fn create_emitters(config: &SimulationConfig) -> Vec<Emitter> {
+        let spacing = config.particle_spacing as f32;
+        config
+            .emitters
+            .iter()
+            .map(|emitter| Emitter::new(emitter, spacing))
+            .collect()
+    }
+
+    fn create_particles(
+        config: &SimulationConfig,
+        use_random_pos: bool,
+        rng: &mut impl Rng,
+    ) -> Vec<Particle> {
+        let mut particles = if use_random_pos {
+            Self::create_particles_random_pos(config, rng)
+        } else {
+            Self::create_particles_grid(config)
+        };
+        Self::assign_materials(&mut particles, &config.materials, rng);
+        particles
+    }
+
+    /// Gives every particle a material drawn by `fraction`. The RNG is only
+    /// used when there is more than one material to choose from.
+    fn assign_materials(particles: &mut [Particle], materials: &[Material], rng: &mut impl Rng) {
+        let weights = WeightedIndex::new(materials.iter().map(|material| material.fraction))
+            .expect("materials are validated to have a positive fraction");
+        for particle in particles {
+            particle.material = if materials.len() > 1 {
+                weights.sample(rng)
+            } else {
+                0
+            };
+            particle.mass = materials[particle.material].mass;
         }
     }
 
-    fn create_particles_grid() -> Vec<Particle> {
-        let particle_amt = constants::PARTICLE_AMT;
-        let spacing = constants::PARTICLE_SPACING as i32;
+    fn create_particles_grid(config: &SimulationConfig) -> Vec<Particle> {
+        let particle_amt = config.particle_amt;
+        let spacing = config.particle_spacing as i32;
         let rows = (particle_amt as f32).sqrt().ceil() as i32;
         let grid_size = Vector::new(
             (rows * spacing) as f32,
             (particle_amt as i32 * spacing / rows) as f32,
         );
 
-        let offset = (Vector::from(constants::WINDOW_SIZE) - grid_size) / 2.0;
+        let offset = (Vector::from(config.window_size) - grid_size) / 2.0;
 
         (0..particle_amt)
             .map(|i| {
@@ -76,73 +209,315 @@ impl GameContext {
             .collect::<Vec<_>>()
     }
 
-    fn create_particles_random_pos() -> Vec<Particle> {
-        let window_size = constants::WINDOW_SIZE;
-        let radius = Particle::RADIUS;
-        let mut particles =
-            vec![Particle::new((0, 0), (0.0, 0.0)); constants::PARTICLE_AMT as usize];
-        let mut rng = rand::thread_rng();
+    fn create_particles_random_pos(config: &SimulationConfig, rng: &mut impl Rng) -> Vec<Particle> {
+        let window_size = config.window_size;
+        let radius = config.particle_radius;
+        let mut particles = vec![Particle::new((0, 0), (0.0, 0.0)); config.particle_amt];
 
-        for i in 0..constants::PARTICLE_AMT {
-            let pos = loop {
+        for i in 0..config.particle_amt {
+            let mut pos = (0, 0);
+            for _ in 0..Self::MAX_PLACEMENT_ATTEMPTS {
                 // let x = rng.gen_range(0..window_size.0) as f32;
                 // let y = rng.gen_range(0..window_size.1) as f32;
                 let x = rng.gen_range((radius)..(window_size.0 - radius)) as f32;
                 let y = rng.gen_range((radius)..(window_size.1 - radius)) as f32;
+                pos = (x as i32, y as i32);
 
-                let is_overlap = particles.iter().any(|particle| {
+                let is_overlap = particles[..i].iter().any(|particle| {
                     let dx = particle.position.x - x;
                     let dy = particle.position.y - y;
-                    (dx * dx + dy * dy).sqrt() < Particle::RADIUS as f32 * 2.0
+                    (dx * dx + dy * dy).sqrt() < radius as f32 * 2.0
                 });
 
                 if !is_overlap {
-                    break (x as i32, y as i32);
+                    break;
                 }
-            };
+            }
 
-            particles[i as usize] = Particle::new(pos, (0.0, 0.0));
+            particles[i] = Particle::new(pos, (0.0, 0.0));
         }
 
         particles
     }
 
+    /// Advances every particle by `delta_time` with the current [`Solver`].
     pub fn update(&mut self, cursor: Cursor, delta_time: f32) {
-        self.particles_lookup.update_cells();
+        // Each particle draws from its own stream derived from one value of the
+        // shared RNG, so results do not depend on how the work is split up.
+        let step_seed: u64 = self.rng.gen();
+
+        // The solver refiles the particles before its first query.
+        for emitter in &mut self.emitters {
+            emitter.emit(
+                delta_time,
+                &self.config,
+                &mut self.particles_lookup.particles,
+            );
+        }
+        self.update_boundary();
+        match self.config.integrator {
+            Integrator::SymplecticEuler => self.run_solver(cursor, delta_time, step_seed),
+            Integrator::VelocityVerlet => self.update_verlet(cursor, delta_time, step_seed),
+            Integrator::Rk2 => self.update_midpoint(cursor, delta_time, step_seed),
+        }
+        self.update_rigid_bodies(delta_time);
+        self.drain_particles();
+
+        self.delta_time = delta_time;
+    }
+
+    /// Drops the particles that left the window through an open side or
+    /// entered a drain.
+    fn drain_particles(&mut self) {
+        let config = &self.config;
+        self.particles_lookup.retain(|particle| {
+            !config
+                .walls
+                .is_outflow(particle.position, config.window_size)
+                && config
+                    .drains
+                    .iter()
+                    .all(|drain| drain.signed_distance(particle.position).0 >= 0.0)
+        });
+    }
+
+    /// Adds `particles` until `max_particles` is reached and returns how many
+    /// were added. Every particle's material must exist in `config`.
+    pub fn add_particles(&mut self, particles: impl IntoIterator<Item = Particle>) -> usize {
+        let count = self.particles_lookup.particles.len();
+        let room = self.config.max_particles.saturating_sub(count);
+        self.particles_lookup
+            .extend(particles.into_iter().take(room));
+        self.particles_lookup.particles.len() - count
+    }
+
+    /// Removes every particle for which `remove` returns true and returns how
+    /// many were removed. The others keep their order.
+    pub fn remove_particles(&mut self, mut remove: impl FnMut(&Particle) -> bool) -> usize {
+        self.particles_lookup.retain(|particle| !remove(particle))
+    }
+
+    fn run_solver(&mut self, cursor: Cursor, delta_time: f32, step_seed: u64) {
+        self.solver_stats = self.solver.step(SolverStep {
+            lookup: &mut self.particles_lookup,
+            boundary: &self.boundary,
+            executor: &self.executor,
+            config: &self.config,
+            kernels: &self.kernels,
+            cursor,
+            delta_time,
+            step_seed,
+        });
+    }
 
-        for i in 0..constants::PARTICLE_AMT {
-            let (other_particles, current_option) =
-                self.particles_lookup.query_radius(None, None, Some(i));
-            if let Some(current) = current_option {
-                current.update(&other_particles, cursor, delta_time);
+    /// Has the solver take a step of zero length, which leaves the particles
+    /// where they are and stores the acceleration at their current position and
+    /// velocity in `Particle::acceleration`.
+    fn evaluate_accelerations(&mut self, cursor: Cursor, step_seed: u64) {
+        self.run_solver(cursor, 0.0, step_seed);
+    }
+
+    /// Velocity Verlet step: half a kick with the acceleration left by the last
+    /// step, the drift, and the other half kick with the acceleration there.
+    fn update_verlet(&mut self, cursor: Cursor, delta_time: f32, step_seed: u64) {
+        let config = &self.config;
+        self.executor
+            .for_each_mut(&mut self.particles_lookup.particles, |_, particle| {
+                let velocity = particle.velocity + particle.acceleration * (delta_time * 0.5);
+                particle.advance(velocity, velocity, delta_time, config);
+            });
+        self.evaluate_accelerations(cursor, step_seed);
+        self.executor
+            .for_each_mut(&mut self.particles_lookup.particles, |_, particle| {
+                particle.velocity += particle.acceleration * (delta_time * 0.5);
+            });
+    }
+
+    /// RK2 step: the acceleration at the start state takes every particle half
+    /// a step to the midpoint, where the acceleration is evaluated again. The
+    /// particles are finally moved from the start state with the midpoint
+    /// velocity and acceleration.
+    fn update_midpoint(&mut self, cursor: Cursor, delta_time: f32, step_seed: u64) {
+        self.start_states.clear();
+        self.start_states.extend(
+            self.particles_lookup
+                .particles
+                .iter()
+                .map(|particle| (particle.position, particle.velocity)),
+        );
+
+        self.evaluate_accelerations(cursor, step_seed);
+        let config = &self.config;
+        self.executor
+            .for_each_mut(&mut self.particles_lookup.particles, |_, particle| {
+                let velocity = particle.velocity + particle.acceleration * (delta_time * 0.5);
+                particle.advance(particle.velocity, velocity, delta_time * 0.5, config);
+            });
+        self.midpoint_velocities.clear();
+        self.midpoint_velocities.extend(
+            self.particles_lookup
+                .particles
+                .iter()
+                .map(|particle| particle.velocity),
+        );
+        self.evaluate_accelerations(cursor, step_seed);
+
+        let start_states = &self.start_states;
+        let midpoint_velocities = &self.midpoint_velocities;
+        let config = &self.config;
+        self.executor
+            .for_each_mut(&mut self.particles_lookup.particles, |i, particle| {
+                (particle.position, particle.velocity) = start_states[i];
+                let final_velocity = particle.velocity + particle.acceleration * delta_time;
+                particle.advance(midpoint_velocities[i], final_velocity, delta_time, config);
+            });
+    }
+
+    /// Samples the walls and the rigid body outlines in their current pose.
+    fn update_boundary(&mut self) {
+        self.boundary.particles.clear();
+        self.boundary.particles.extend_from_slice(&self.walls);
+        for (index, body) in self.rigid_bodies.iter().enumerate() {
+            self.boundary
+                .particles
+                .extend(body.boundary_particles(index));
+        }
+        self.boundary.update_cells();
+    }
+
+    /// Applies the reaction to the solver's boundary pressure to every rigid
+    /// body, resolves particles that still ended up inside and moves the body.
+    fn update_rigid_bodies(&mut self, delta_time: f32) {
+        // Each particle pushed sample `b` with `boundary_pressure V_b ∇W`, from
+        // where the solver saw it.
+        self.particles_lookup.sync_ghosts();
+        let lookup = &self.particles_lookup;
+        let kernels = &self.kernels;
+        let samples = &self.boundary.particles;
+        self.executor
+            .map_into(&mut self.boundary_forces, samples.len(), |b| {
+                let sample = &samples[b];
+                let mut force = Vector::zero();
+                if sample.body.is_none() {
+                    return force;
+                }
+                for particle in lookup.query_around(sample.position).particles() {
+                    let gradient =
+                        kernel_gradient(kernels, particle.predicted_position - sample.position);
+                    force += gradient * particle.boundary_pressure;
+                }
+                force * sample.volume
+            });
+        for body in &mut self.rigid_bodies {
+            body.fluid_force = Vector::zero();
+            body.fluid_torque = 0.0;
+        }
+        for (sample, &force) in samples.iter().zip(&self.boundary_forces) {
+            if let Some(index) = sample.body {
+                let body = &mut self.rigid_bodies[index];
+                body.fluid_force += force;
+                body.fluid_torque += (sample.position - body.position).cross(force);
             }
         }
+
+        // The grid was built from the predicted positions before this step;
+        // pad the query so particles that moved since are still found.
+        let padding = self.config.particle_radius as f32 + self.particles_lookup.cell_size();
+        for body in &mut self.rigid_bodies {
+            body.apply_forces(delta_time, &self.config);
+
+            self.body_contacts.clear();
+            self.body_contacts.extend(
+                self.particles_lookup
+                    .query_radius(body.position, body.bounding_radius() + padding)
+                    .map(|(index, _)| index),
+            );
+            body.collide_particles(
+                &mut self.particles_lookup.particles,
+                &self.body_contacts,
+                delta_time,
+                &self.config,
+            );
+
+            body.advance(delta_time, &self.config);
+        }
+    }
+
+    /// Largest step that satisfies both the CFL condition `Δt ≤ λ h / v_max`
+    /// and the force criterion `Δt ≤ λ √(h / a_max)`, where `λ` is `cfl_factor`
+    /// and `h` the smoothing radius, clamped to `min_timestep..=max_timestep`.
+    pub fn stable_timestep(&self) -> f32 {
+        let radius = self.config.smoothing_radius as f32;
+        let (max_velocity, max_acceleration) = self.particles_lookup.particles.iter().fold(
+            (0.0f32, 0.0f32),
+            |(velocity, acceleration), particle| {
+                (
+                    velocity.max(particle.velocity.magnitude()),
+                    acceleration.max(particle.acceleration.magnitude()),
+                )
+            },
+        );
+
+        let mut delta_time = self.config.max_timestep;
+        if max_velocity > 0.0 {
+            delta_time = delta_time.min(self.config.cfl_factor * radius / max_velocity);
+        }
+        if max_acceleration > 0.0 {
+            delta_time =
+                delta_time.min(self.config.cfl_factor * (radius / max_acceleration).sqrt());
+        }
+        delta_time.max(self.config.min_timestep)
+    }
+
+    pub fn solver(&self) -> &dyn Solver {
+        self.solver.as_ref()
+    }
+
+    /// Switches to one of the built-in solvers and records it in `config`. A
+    /// material derived from the global constants gets the rest density of
+    /// the new solver, and the iterative solvers fall back to
+    /// `Integrator::SymplecticEuler`.
+    pub fn set_solver_type(&mut self, solver: SolverType) {
+        self.config.solver = solver;
+        if solver != SolverType::Explicit {
+            self.config.integrator = Integrator::SymplecticEuler;
+        }
+        if self.derived_material {
+            self.config.materials[0].rest_density = Material::default_rest_density(&self.config);
+        }
+        self.solver = solver.build();
+        self.solver_stats = SolverStats::default();
+    }
+
+    /// Switches to a custom solver. `config.solver` keeps its previous value.
+    pub fn set_solver(&mut self, solver: Box<dyn Solver>) {
+        self.solver = solver;
+        self.solver_stats = SolverStats::default();
     }
 
     pub fn update_heatmap(&mut self) {
-        for x in 0..self.heatmap.len() {
-            for y in 0..self.heatmap[0].len() {
-                let point = Vector::new(x as f32, y as f32) * self.heatmap_resolution as f32;
-                let (other_particles, _) = self.particles_lookup.query_radius(
-                    Some(point),
-                    Some(Particle::SMOOTHING_RADIUS as f32),
-                    None,
-                );
-
-                let density = calculate_density(point, &other_particles);
-                self.heatmap[x][y] = density;
+        let radius = self.kernels.density.support_radius();
+        let resolution = self.heatmap_resolution as f32;
+        let lookup = &self.particles_lookup;
+        let kernel = self.kernels.density.as_ref();
+        self.executor.for_each_mut(&mut self.heatmap, |x, column| {
+            for (y, value) in column.iter_mut().enumerate() {
+                let point = Vector::new(x as f32, y as f32) * resolution;
+                let other_particles = lookup.query_radius(point, radius).particles();
+                *value = calculate_density(point, other_particles, kernel);
             }
-        }
+        });
     }
 
+    /// Restores the initial particles, rigid bodies and emitters and restarts the
+    /// random sequence from `seed`, so a reset run replays exactly like the first one.
     pub fn reset(&mut self, use_random_pos: bool) {
-        let particles = if use_random_pos {
-            Self::create_particles_random_pos()
-        } else {
-            Self::create_particles_grid()
-        };
-
-        self.particles_lookup.particles = particles;
+        self.rng = StdRng::seed_from_u64(self.seed);
+        self.particles_lookup.particles =
+            Self::create_particles(&self.config, use_random_pos, &mut self.rng);
+        self.rigid_bodies = Self::create_rigid_bodies(&self.config, &self.kernels);
+        self.emitters = Self::create_emitters(&self.config);
+        self.update_boundary();
 
         self.particles_lookup.update_cells();
 
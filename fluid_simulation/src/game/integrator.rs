@@ -0,0 +1,23 @@
+use serde::Deserialize;
+
+/// How a particle's position and velocity are advanced from its acceleration.
+///
+/// `VelocityVerlet` and `Rk2` evaluate accelerations by running the solver for
+/// steps of zero length. The iterative solvers correct positions and velocities
+/// within the step and only work with `SymplecticEuler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Integrator {
+    /// `v += a Δt`, then `x += v Δt`. First order, but symplectic.
+    SymplecticEuler,
+    /// Velocity Verlet: `x += v Δt + ½ a Δt²` and `v += ½ (a + a') Δt`, with
+    /// `a'` evaluated at the new positions. `a` is the one left by the previous
+    /// step, so particles start their first step without it.
+    #[serde(alias = "leapfrog")]
+    VelocityVerlet,
+    /// Midpoint Runge-Kutta: the acceleration at the start takes the particles
+    /// half a step to the midpoint, and the full step is taken with the
+    /// midpoint's velocity and acceleration. Costs two solver evaluations per
+    /// step.
+    Rk2,
+}
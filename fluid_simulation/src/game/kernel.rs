@@ -0,0 +1,365 @@
+use std::f32::consts::PI;
+use std::fmt::Debug;
+
+use serde::Deserialize;
+
+/// Radially symmetric SPH smoothing kernel in 2D.
+///
+/// All functions take the distance between two particles and are zero at and
+/// beyond `support_radius`. Every kernel is normalised so that its integral over
+/// the plane is 1.
+pub trait Kernel: Debug + Send + Sync {
+    fn support_radius(&self) -> f32;
+
+    fn value(&self, dst: f32) -> f32;
+
+    /// Radial derivative `dW/dr`; the gradient vector is this times the unit offset.
+    fn gradient(&self, dst: f32) -> f32;
+
+    /// `∇²W = d²W/dr² + (1/r) dW/dr`.
+    fn laplacian(&self, dst: f32) -> f32;
+}
+
+/// Distance used in place of `0` where a Laplacian has a `1/r` singularity.
+fn non_zero(dst: f32, radius: f32) -> f32 {
+    dst.max(radius * 1e-4)
+}
+
+/// Müller et al. poly6 kernel, `(h² - r²)³`. Smooth at the origin, which makes it
+/// a good fit for density and viscosity but its gradient vanishes as `r → 0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Poly6 {
+    pub radius: f32,
+}
+
+impl Kernel for Poly6 {
+    fn support_radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn value(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        if dst >= h {
+            return 0.0;
+        }
+        let volume = (PI * h.powi(8)) / 4.0;
+        (h * h - dst * dst).powi(3) / volume
+    }
+
+    fn gradient(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        if dst >= h {
+            return 0.0;
+        }
+        -24.0 * dst * (h * h - dst * dst).powi(2) / (PI * h.powi(8))
+    }
+
+    fn laplacian(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        if dst >= h {
+            return 0.0;
+        }
+        let (h2, r2) = (h * h, dst * dst);
+        -48.0 * (h2 - r2) * (h2 - 3.0 * r2) / (PI * h.powi(8))
+    }
+}
+
+/// Desbrun's spiky kernel, `(h - r)³`, whose gradient does not vanish at the
+/// origin and therefore keeps particles from clustering under pressure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spiky {
+    pub radius: f32,
+}
+
+impl Kernel for Spiky {
+    fn support_radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn value(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        if dst >= h {
+            return 0.0;
+        }
+        let volume = (PI * h.powi(5)) / 10.0;
+        (h - dst).powi(3) / volume
+    }
+
+    fn gradient(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        if dst >= h {
+            return 0.0;
+        }
+        -30.0 * (h - dst).powi(2) / (PI * h.powi(5))
+    }
+
+    fn laplacian(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        if dst >= h {
+            return 0.0;
+        }
+        let r = non_zero(dst, h);
+        30.0 * (h - r) * (3.0 * r - h) / (PI * h.powi(5) * r)
+    }
+}
+
+/// Quadratic spiky kernel, `(h - r)²`. The simulation's original density and
+/// pressure kernel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpikyPow2 {
+    pub radius: f32,
+}
+
+impl Kernel for SpikyPow2 {
+    fn support_radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn value(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        if dst >= h {
+            return 0.0;
+        }
+        // Integrate[(s-x)^2x,{x,0,s},{θ,0,2π}]
+        let volume = (PI * h.powi(4)) / 6.0;
+        (h - dst).powi(2) / volume
+    }
+
+    fn gradient(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        if dst >= h {
+            return 0.0;
+        }
+        let scale = 12.0 / (h.powi(4) * PI);
+        (dst - h) * scale
+    }
+
+    fn laplacian(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        if dst >= h {
+            return 0.0;
+        }
+        let r = non_zero(dst, h);
+        12.0 * (2.0 * r - h) / (PI * h.powi(4) * r)
+    }
+}
+
+/// Monaghan's cubic B-spline, rescaled so its support is `radius` rather than `2h`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CubicSpline {
+    pub radius: f32,
+}
+
+impl CubicSpline {
+    fn sigma(&self) -> f32 {
+        40.0 / (7.0 * PI * self.radius * self.radius)
+    }
+}
+
+impl Kernel for CubicSpline {
+    fn support_radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn value(&self, dst: f32) -> f32 {
+        let q = dst / self.radius;
+        if q >= 1.0 {
+            0.0
+        } else if q <= 0.5 {
+            self.sigma() * (6.0 * (q * q * q - q * q) + 1.0)
+        } else {
+            self.sigma() * 2.0 * (1.0 - q).powi(3)
+        }
+    }
+
+    fn gradient(&self, dst: f32) -> f32 {
+        let q = dst / self.radius;
+        let scale = self.sigma() / self.radius;
+        if q >= 1.0 {
+            0.0
+        } else if q <= 0.5 {
+            scale * 6.0 * (3.0 * q * q - 2.0 * q)
+        } else {
+            scale * -6.0 * (1.0 - q).powi(2)
+        }
+    }
+
+    fn laplacian(&self, dst: f32) -> f32 {
+        let q = dst / self.radius;
+        let scale = self.sigma() / (self.radius * self.radius);
+        if q >= 1.0 {
+            0.0
+        } else if q <= 0.5 {
+            scale * 6.0 * ((6.0 * q - 2.0) + (3.0 * q - 2.0))
+        } else {
+            let q_safe = non_zero(q, 1.0);
+            scale * (12.0 * (1.0 - q) - 6.0 * (1.0 - q).powi(2) / q_safe)
+        }
+    }
+}
+
+/// Wendland C2 kernel, `(1 - q)⁴ (1 + 4q)`. Positive-definite Fourier transform,
+/// so it does not suffer from pairing instability at large neighbour counts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WendlandC2 {
+    pub radius: f32,
+}
+
+impl Kernel for WendlandC2 {
+    fn support_radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn value(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        let q = dst / h;
+        if q >= 1.0 {
+            return 0.0;
+        }
+        7.0 / (PI * h * h) * (1.0 - q).powi(4) * (1.0 + 4.0 * q)
+    }
+
+    fn gradient(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        let q = dst / h;
+        if q >= 1.0 {
+            return 0.0;
+        }
+        -140.0 * q * (1.0 - q).powi(3) / (PI * h.powi(3))
+    }
+
+    fn laplacian(&self, dst: f32) -> f32 {
+        let h = self.radius;
+        let q = dst / h;
+        if q >= 1.0 {
+            return 0.0;
+        }
+        -140.0 * (1.0 - q).powi(2) * (2.0 - 5.0 * q) / (PI * h.powi(4))
+    }
+}
+
+/// Akinci et al. cohesion spline. Attractive beyond `radius / 2` and slightly
+/// repulsive closer in, so it pulls particles towards a preferred spacing.
+/// Normalised to integrate to 1 over the plane like the kernels above.
+pub fn cohesion_spline(dst: f32, radius: f32) -> f32 {
+    let h = radius;
+    if dst >= h || dst <= 0.0 {
+        return 0.0;
+    }
+    let sigma = 35840.0 / (209.0 * PI * h.powi(8));
+    let value = (h - dst).powi(3) * dst.powi(3);
+    if 2.0 * dst > h {
+        sigma * value
+    } else {
+        sigma * (2.0 * value - h.powi(6) / 64.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KernelType {
+    Poly6,
+    Spiky,
+    SpikyPow2,
+    CubicSpline,
+    WendlandC2,
+}
+
+impl KernelType {
+    pub fn build(self, radius: f32) -> Box<dyn Kernel> {
+        match self {
+            KernelType::Poly6 => Box::new(Poly6 { radius }),
+            KernelType::Spiky => Box::new(Spiky { radius }),
+            KernelType::SpikyPow2 => Box::new(SpikyPow2 { radius }),
+            KernelType::CubicSpline => Box::new(CubicSpline { radius }),
+            KernelType::WendlandC2 => Box::new(WendlandC2 { radius }),
+        }
+    }
+}
+
+/// The kernels a simulation evaluates: one for density and pressure, one for
+/// viscosity and the sharper [`Spiky`] kernel for near-density.
+#[derive(Debug)]
+pub struct Kernels {
+    pub density: Box<dyn Kernel>,
+    pub near_density: Box<dyn Kernel>,
+    pub viscosity: Box<dyn Kernel>,
+}
+
+impl Kernels {
+    pub fn new(density: KernelType, viscosity: KernelType, radius: f32) -> Kernels {
+        Kernels {
+            density: density.build(radius),
+            near_density: KernelType::Spiky.build(radius),
+            viscosity: viscosity.build(radius),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [KernelType; 5] = [
+        KernelType::Poly6,
+        KernelType::Spiky,
+        KernelType::SpikyPow2,
+        KernelType::CubicSpline,
+        KernelType::WendlandC2,
+    ];
+
+    /// Midpoint rule for `∫₀ʰ W(r) 2πr dr`, done in f64 to keep rounding out of the result.
+    fn integrate(kernel: &dyn Kernel) -> f64 {
+        let steps = 100_000;
+        let h = kernel.support_radius() as f64;
+        let dr = h / steps as f64;
+        (0..steps)
+            .map(|i| {
+                let r = (i as f64 + 0.5) * dr;
+                kernel.value(r as f32) as f64 * 2.0 * std::f64::consts::PI * r * dr
+            })
+            .sum()
+    }
+
+    #[test]
+    fn kernels_integrate_to_one_in_2d() {
+        for kernel_type in ALL {
+            for radius in [1.0, 20.0] {
+                let integral = integrate(kernel_type.build(radius).as_ref());
+                assert!(
+                    (integral - 1.0).abs() < 1e-3,
+                    "{:?} with radius {} integrates to {}",
+                    kernel_type,
+                    radius,
+                    integral
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cohesion_spline_integrates_to_one_in_2d() {
+        let steps = 100_000;
+        let h = 20.0;
+        let dr = h / steps as f64;
+        let integral: f64 = (0..steps)
+            .map(|i| {
+                let r = (i as f64 + 0.5) * dr;
+                cohesion_spline(r as f32, h as f32) as f64 * 2.0 * std::f64::consts::PI * r * dr
+            })
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-3, "integrates to {}", integral);
+    }
+
+    #[test]
+    fn kernels_vanish_outside_support() {
+        for kernel_type in ALL {
+            let kernel = kernel_type.build(20.0);
+            for dst in [20.0, 25.0] {
+                assert_eq!(kernel.value(dst), 0.0, "{:?}", kernel_type);
+                assert_eq!(kernel.gradient(dst), 0.0, "{:?}", kernel_type);
+                assert_eq!(kernel.laplacian(dst), 0.0, "{:?}", kernel_type);
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use serde::Deserialize;
+
+use crate::config::SimulationConfig;
+use crate::constants;
+
+use super::{
+    kernel::Kernels,
+    particle::Particle,
+    solver::{prototype_density, SolverType},
+};
+
+/// One kind of fluid. Particles refer to their material by index into
+/// `SimulationConfig::materials`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Material {
+    pub name: String,
+    pub mass: f32,
+    /// Replaces `density_floor` for particles of this material. Keep
+    /// `rest_density / mass` equal across materials so they pack at the same
+    /// spacing; the heavier one then sinks.
+    pub rest_density: f32,
+    /// Replaces `viscosity_constant`.
+    pub viscosity: f32,
+    /// Replaces `pressure_constant` of the linear equation of state. Density is
+    /// proportional to mass, so keep `stiffness * mass` equal across materials
+    /// for the same response to compression.
+    pub stiffness: f32,
+    pub color: (u8, u8, u8),
+    /// Relative share of the initial particles.
+    #[serde(default = "Material::default_fraction")]
+    pub fraction: f32,
+}
+
+impl Material {
+    /// The only material of a config without `[[materials]]`, built from the
+    /// global constants.
+    pub fn from_config(config: &SimulationConfig) -> Material {
+        Material {
+            name: "fluid".to_string(),
+            mass: Particle::MASS,
+            rest_density: Self::default_rest_density(config),
+            viscosity: config.viscosity_constant,
+            stiffness: config.pressure_constant,
+            color: constants::PARTICLE_COLOR,
+            fraction: Self::default_fraction(),
+        }
+    }
+
+    /// Rest density of [`Self::from_config`]. The explicit solver treats
+    /// `density_floor` as rest density and lets the fluid compress a little past
+    /// it, while the iterative solvers drive every particle to its rest density
+    /// exactly and need the density fluid actually has when packed at
+    /// `particle_spacing`.
+    pub fn default_rest_density(config: &SimulationConfig) -> f32 {
+        match config.solver {
+            SolverType::Explicit => config.density_floor,
+            _ => {
+                let kernels = Kernels::new(
+                    config.kernel,
+                    config.viscosity_kernel,
+                    config.smoothing_radius as f32,
+                );
+                Particle::MASS * prototype_density(config, &kernels)
+            }
+        }
+    }
+
+    fn default_fraction() -> f32 {
+        1.0
+    }
+}
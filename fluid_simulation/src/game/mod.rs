@@ -1,6 +1,23 @@
+pub mod boundary;
 pub mod cursor;
+pub mod dfsph;
+pub mod emitter;
+pub mod equation_of_state;
+pub mod executor;
+pub mod explicit;
+pub mod force_model;
 pub mod game_context;
+pub mod iisph;
+pub mod integrator;
+pub mod kernel;
+pub mod material;
+pub mod obstacle;
 pub mod particle;
 pub mod particles_lookup;
+pub mod pbf;
+pub mod pcisph;
+pub mod rigid_body;
+pub mod solver;
 pub mod utils;
 pub mod vector;
+pub mod walls;
@@ -0,0 +1,153 @@
+use serde::Deserialize;
+
+use super::vector::Vector;
+
+/// A static collider inside the window. Particles are kept outside of it using
+/// its signed distance and outward normal.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Obstacle {
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+    },
+    /// Axis-aligned box; `size` is the full width and height.
+    Box {
+        center: (f32, f32),
+        size: (f32, f32),
+    },
+    /// Closed polygon through `points` in either winding order.
+    Polygon {
+        points: Vec<(f32, f32)>,
+    },
+}
+
+impl Obstacle {
+    /// Distance from `point` to the obstacle surface, negative inside, and the
+    /// outward surface normal there. The normal is zero if it is undefined,
+    /// e.g. at the centre of a circle.
+    pub fn signed_distance(&self, point: Vector) -> (f32, Vector) {
+        match self {
+            Obstacle::Circle { center, radius } => {
+                let offset = point - Vector::from(*center);
+                (offset.magnitude() - radius, offset.normalize())
+            }
+            Obstacle::Box { center, size } => {
+                let offset = point - Vector::from(*center);
+                let half_size = Vector::from(*size) / 2.0;
+                let q = Vector::new(offset.x.abs(), offset.y.abs()) - half_size;
+                let sign = Vector::new(offset.x.signum(), offset.y.signum());
+                if q.x > 0.0 || q.y > 0.0 {
+                    let outside = q.max(0.0);
+                    let normal = Vector::new(outside.x * sign.x, outside.y * sign.y);
+                    (outside.magnitude(), normal.normalize())
+                } else if q.x > q.y {
+                    (q.x, Vector::new(sign.x, 0.0))
+                } else {
+                    (q.y, Vector::new(0.0, sign.y))
+                }
+            }
+            Obstacle::Polygon { points } => polygon_distance(points, point),
+        }
+    }
+}
+
+/// Signed distance and outward normal of a closed polygon, see
+/// [`Obstacle::signed_distance`].
+pub fn polygon_distance<P: Copy + Into<Vector>>(points: &[P], point: Vector) -> (f32, Vector) {
+    let mut distance = f32::INFINITY;
+    let mut closest = point;
+    let mut inside = false;
+    for (i, &end) in points.iter().enumerate() {
+        let start: Vector = points[(i + points.len() - 1) % points.len()].into();
+        let end: Vector = end.into();
+
+        let edge = end - start;
+        let length = edge.dot(edge);
+        let t = if length > 0.0 {
+            ((point - start).dot(edge) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let candidate = start + edge * t;
+        let candidate_distance = (point - candidate).magnitude();
+        if candidate_distance < distance {
+            distance = candidate_distance;
+            closest = candidate;
+        }
+
+        // Even-odd rule on a ray towards +x.
+        if (start.y > point.y) != (end.y > point.y)
+            && point.x < start.x + (point.y - start.y) / (end.y - start.y) * edge.x
+        {
+            inside = !inside;
+        }
+    }
+    let normal = (point - closest).normalize();
+    if inside {
+        (-distance, normal * -1.0)
+    } else {
+        (distance, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_distance(obstacle: &Obstacle, point: (f32, f32), distance: f32, normal: (f32, f32)) {
+        let (actual_distance, actual_normal) = obstacle.signed_distance(Vector::from(point));
+        assert!(
+            (actual_distance - distance).abs() < 1e-4
+                && (actual_normal - Vector::from(normal)).magnitude() < 1e-4,
+            "{:?} at {:?}: got {} along {:?}, expected {} along {:?}",
+            obstacle,
+            point,
+            actual_distance,
+            actual_normal,
+            distance,
+            normal
+        );
+    }
+
+    #[test]
+    fn box_distance_inside_outside_and_on_the_surface() {
+        let obstacle = Obstacle::Box {
+            center: (0.0, 0.0),
+            size: (20.0, 10.0),
+        };
+        // Inside, nearest to the bottom edge.
+        assert_distance(&obstacle, (1.0, 3.0), -2.0, (0.0, 1.0));
+        // Outside, beside the right edge.
+        assert_distance(&obstacle, (15.0, 0.0), 5.0, (1.0, 0.0));
+        // On the left edge.
+        assert_distance(&obstacle, (-10.0, 2.0), 0.0, (-1.0, 0.0));
+        // Outside, diagonally off a corner.
+        assert_distance(&obstacle, (13.0, -9.0), 5.0, (0.6, -0.8));
+    }
+
+    #[test]
+    fn polygon_distance_inside_outside_and_on_the_surface() {
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        for points in [square.to_vec(), square.iter().rev().copied().collect()] {
+            let obstacle = Obstacle::Polygon { points };
+            assert_distance(&obstacle, (5.0, 3.0), -3.0, (0.0, -1.0));
+            assert_distance(&obstacle, (15.0, 5.0), 5.0, (1.0, 0.0));
+            assert_eq!(obstacle.signed_distance(Vector::new(10.0, 5.0)).0, 0.0);
+            assert_distance(&obstacle, (13.0, 14.0), 5.0, (0.6, 0.8));
+        }
+
+        let triangle = Obstacle::Polygon {
+            points: vec![(0.0, 0.0), (20.0, 0.0), (0.0, 20.0)],
+        };
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        assert_distance(&triangle, (6.0, 5.0), -5.0, (0.0, -1.0));
+        assert_distance(
+            &triangle,
+            (15.0, 15.0),
+            10.0 * diagonal,
+            (diagonal, diagonal),
+        );
+        assert_distance(&triangle, (-3.0, -4.0), 5.0, (-0.6, -0.8));
+    }
+}
@@ -1,77 +1,221 @@
-use std::f32::consts::PI;
-
 use rand::Rng;
-use sdl2::pixels::Color;
 
+use crate::config::SimulationConfig;
 use crate::constants;
-use crate::game::utils::calculate_density;
 use crate::game::vector::Vector;
 
 use super::{
-    cursor::{self, Cursor, CursorForceType},
+    boundary::BoundaryParticle,
+    cursor::{Cursor, CursorForceType},
+    force_model::ForceModel,
+    kernel::{cohesion_spline, Kernels},
+    solver::kernel_gradient,
     utils::{
-        calculate_shared_pressure, random_direction, smoothing_kernel, smoothing_kernel_derivative,
-        viscosity_smoothing_kernel,
+        calculate_shared_pressure, density_to_pressure, near_density_to_pressure, random_direction,
     },
+    walls::WallMode,
 };
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Particle {
     pub position: Vector,
     pub velocity: Vector,
+    /// Acceleration applied by the last `integrate`, before collisions.
+    pub acceleration: Vector,
     pub density: f32,
+    pub near_density: f32,
+    pub pressure: f32,
+    /// Pressure term the particle pushed boundary samples with during the last
+    /// step: sample `b` received `boundary_pressure V_b ∇W(x - x_b)`.
+    pub boundary_pressure: f32,
+    pub surface_normal: Vector,
     pub predicted_position: Vector,
+    /// Index into `SimulationConfig::materials`.
+    pub material: usize,
+    /// Copied from the material so neighbour sums need not look it up.
+    pub mass: f32,
 }
 
 impl Particle {
-    pub const RADIUS: u32 = constants::PARTICLE_RADIUS;
-    pub const SMOOTHING_RADIUS: u32 = constants::SMOOTHING_RADIUS;
-    pub const COLOR: Color = Color::BLUE;
+    /// Mass of the default material.
     pub const MASS: f32 = 1.0;
 
     pub fn new(position: (i32, i32), velocity: (f32, f32)) -> Particle {
         Particle {
             position: Vector::from(position),
             velocity: Vector::from(velocity),
+            acceleration: Vector::zero(),
             density: 0.0,
+            near_density: 0.0,
+            pressure: 0.0,
+            boundary_pressure: 0.0,
+            surface_normal: Vector::zero(),
             predicted_position: Vector::from(position),
+            material: 0,
+            mass: Self::MASS,
+        }
+    }
+
+    /// A particle of `material`, which must exist in `config`.
+    pub fn spawn(
+        position: Vector,
+        velocity: Vector,
+        material: usize,
+        config: &SimulationConfig,
+    ) -> Particle {
+        Particle {
+            position,
+            velocity,
+            predicted_position: position,
+            material,
+            mass: config.material(material).mass,
+            ..Particle::new((0, 0), (0.0, 0.0))
         }
     }
 
-    pub fn update(&mut self, other_particles: &Vec<Particle>, cursor: Cursor, delta_time: f32) {
-        self.density = self.calculate_density(self.position, other_particles);
-        let acceleration = self.calculate_acceleration(other_particles, cursor);
+    /// Rest density of the particle's material.
+    pub fn rest_density(&self, config: &SimulationConfig) -> f32 {
+        config.material(self.material).rest_density
+    }
+
+    /// Extrapolates where the particle will be after `delta_time`, used by the
+    /// density and force stages so all particles see the same snapshot.
+    pub fn predict(&mut self, delta_time: f32) {
+        self.predicted_position = self.position + self.velocity * delta_time;
+    }
 
+    /// Symplectic Euler step. Velocity Verlet and RK2 are driven by
+    /// `GameContext`, which only has the solver evaluate accelerations for them.
+    pub fn integrate(&mut self, acceleration: Vector, delta_time: f32, config: &SimulationConfig) {
+        self.acceleration = acceleration;
         let final_velocity = self.velocity + acceleration * delta_time;
-        let normal = self.collide(acceleration, delta_time);
-        self.reflect(final_velocity, normal, delta_time);
+        self.advance(final_velocity, final_velocity, delta_time, config);
+    }
+
+    /// Moves by `drift * delta_time` and takes `final_velocity`, both mirrored at
+    /// any obstacle the move would cross. Solid walls push back through their
+    /// boundary samples during the solver step instead; see [`Self::apply_walls`]
+    /// for what happens at the window edges.
+    pub fn advance(
+        &mut self,
+        drift: Vector,
+        final_velocity: Vector,
+        delta_time: f32,
+        config: &SimulationConfig,
+    ) {
+        let normal = self.collide(drift, delta_time, config);
+        self.reflect(drift, final_velocity, normal, delta_time);
+        self.push_out_of_obstacles(config);
+        self.apply_walls(delta_time, config);
     }
 
-    fn collide_bounding(&self, position: Vector) -> Vector {
+    /// Sum of the outward normals of the obstacles that `position` overlaps
+    /// while `drift` still points into them.
+    fn collide_obstacles(
+        &self,
+        position: Vector,
+        drift: Vector,
+        config: &SimulationConfig,
+    ) -> Vector {
+        let radius = config.particle_radius as f32;
         let mut normal = Vector::zero();
-        if (position.x - Self::RADIUS as f32) < 0.0 {
-            normal += Vector::new(1.0, 0.0);
-        } else if (position.x + Self::RADIUS as f32) > constants::WINDOW_SIZE.0 as f32 {
-            normal += Vector::new(-1.0, 0.0);
+        for obstacle in &config.obstacles {
+            let (distance, obstacle_normal) = obstacle.signed_distance(position);
+            if distance < radius && drift.dot(obstacle_normal) < 0.0 {
+                normal += obstacle_normal;
+            }
         }
-        if (position.y - Self::RADIUS as f32) < 0.0 {
-            normal += Vector::new(0.0, 1.0);
-        } else if (position.y + Self::RADIUS as f32) > constants::WINDOW_SIZE.1 as f32 {
-            normal += Vector::new(0.0, -1.0);
+        normal
+    }
+
+    fn collide(&self, drift: Vector, delta_time: f32, config: &SimulationConfig) -> Vector {
+        let new_position = self.position + drift * delta_time;
+        self.collide_obstacles(new_position, drift, config)
+            .normalize()
+            * config.collision_damping
+    }
+
+    /// Projects the particle onto the surface of any obstacle it still overlaps,
+    /// e.g. after a fast move or when it was spawned inside.
+    fn push_out_of_obstacles(&mut self, config: &SimulationConfig) {
+        let radius = config.particle_radius as f32;
+        for obstacle in &config.obstacles {
+            let (distance, normal) = obstacle.signed_distance(self.position);
+            if distance < radius {
+                self.position += normal * (radius - distance);
+            }
         }
-        return normal;
     }
 
-    fn collide(&self, acceleration: Vector, delta_time: f32) -> Vector {
-        let mut normal = Vector::zero();
-        let new_position = self.next_position(acceleration, delta_time);
-        normal += self.collide_bounding(new_position);
-        return normal.normalize() * constants::COLLISION_DAMPING;
+    /// Wraps the particle through periodic sides and applies the restitution
+    /// and friction of solid ones. Particles the wall samples did not stop are
+    /// put back inside the window. Open sides let them go; `GameContext`
+    /// removes them after the step.
+    fn apply_walls(&mut self, delta_time: f32, config: &SimulationConfig) {
+        let size = Vector::from(config.window_size);
+        let walls = &config.walls;
+        (self.position.x, self.velocity.x, self.velocity.y) = Self::apply_wall_pair(
+            (self.position.x, self.velocity.x, self.velocity.y),
+            size.x,
+            (walls.left, walls.right),
+            delta_time,
+            config,
+        );
+        (self.position.y, self.velocity.y, self.velocity.x) = Self::apply_wall_pair(
+            (self.position.y, self.velocity.y, self.velocity.x),
+            size.y,
+            (walls.top, walls.bottom),
+            delta_time,
+            config,
+        );
+    }
+
+    /// One axis of [`Self::apply_walls`], on the position and velocity along it
+    /// and the velocity across it. `sides` are the walls at 0 and `length`.
+    fn apply_wall_pair(
+        (mut position, mut velocity, mut tangential): (f32, f32, f32),
+        length: f32,
+        sides: (WallMode, WallMode),
+        delta_time: f32,
+        config: &SimulationConfig,
+    ) -> (f32, f32, f32) {
+        // Periodic sides are validated to come in pairs.
+        if sides.0 == WallMode::Periodic {
+            return (position.rem_euclid(length), velocity, tangential);
+        }
+
+        let radius = config.particle_radius as f32;
+        let contact = config.smoothing_radius as f32;
+        let walls = [
+            (sides.0, position, radius, 1.0),
+            (sides.1, length - position, length - radius, -1.0),
+        ];
+        for (mode, distance, limit, inward) in walls {
+            if let WallMode::Solid {
+                restitution,
+                friction,
+            } = mode
+            {
+                if distance < contact {
+                    // `friction` is the share lost per default timestep, so
+                    // substeps of any length slow the flow equally.
+                    tangential *= (1.0 - friction).powf(delta_time / constants::TIMESTEP);
+                }
+                if distance < radius {
+                    position = limit;
+                    if velocity * inward < 0.0 {
+                        velocity *= -restitution;
+                    }
+                }
+            }
+        }
+        (position, velocity, tangential)
     }
 
-    fn reflect(&mut self, final_velocity: Vector, normal: Vector, delta_time: f32) {
+    fn reflect(&mut self, drift: Vector, final_velocity: Vector, normal: Vector, delta_time: f32) {
         let new_final_velocity = final_velocity - normal * 2.0 * final_velocity.dot(normal);
-        let displacement = new_final_velocity * delta_time;
+        let new_drift = drift - normal * 2.0 * drift.dot(normal);
+        let displacement = new_drift * delta_time;
 
         let new_position = self.position + displacement;
 
@@ -79,49 +223,86 @@ impl Particle {
         self.position = new_position;
     }
 
-    fn calculate_acceleration(&self, other_particles: &Vec<Particle>, cursor: Cursor) -> Vector {
+    pub fn calculate_acceleration<'a>(
+        &self,
+        other_particles: impl Iterator<Item = &'a Particle> + Clone,
+        boundary_samples: impl Iterator<Item = &'a BoundaryParticle>,
+        cursor: Cursor,
+        config: &SimulationConfig,
+        kernels: &Kernels,
+        rng: &mut impl Rng,
+    ) -> Vector {
+        let pressure_force = match config.force_model {
+            ForceModel::Pressure => {
+                self.calculate_pressure_force(other_particles.clone(), config, kernels, rng)
+            }
+            ForceModel::DoubleDensity => {
+                self.calculate_double_density_force(other_particles.clone(), config, kernels, rng)
+            }
+        } + self.calculate_boundary_pressure_force(
+            boundary_samples,
+            config,
+            kernels,
+        );
+        let acceleration =
+            self.calculate_non_pressure_acceleration(other_particles, cursor, config, kernels, rng);
+        acceleration + pressure_force / (self.density + 1e-3)
+    }
+
+    /// Every acceleration except pressure: gravity, drag, viscosity, surface
+    /// tension and the cursor. Incompressible solvers add their own pressure term.
+    pub fn calculate_non_pressure_acceleration<'a>(
+        &self,
+        other_particles: impl Iterator<Item = &'a Particle> + Clone,
+        cursor: Cursor,
+        config: &SimulationConfig,
+        kernels: &Kernels,
+        rng: &mut impl Rng,
+    ) -> Vector {
         let mut acceleration = Vector::zero();
 
-        let acceleration_gravity = Vector::new(0.0, 1.0) * (constants::GRAVITY as f32);
+        let acceleration_gravity = Vector::new(0.0, 1.0) * config.gravity;
         acceleration += acceleration_gravity;
 
-        let drag_coefficient = constants::DRAG_COEFFICIENT;
+        let drag_coefficient = config.drag_coefficient;
         let drag_force =
             self.velocity.normalize() * -drag_coefficient * self.velocity.magnitude().powi(2);
-        acceleration += drag_force / Self::MASS;
+        acceleration += drag_force / self.mass;
 
-        let pressure_force = self.calculate_pressure_force(other_particles);
-        acceleration += pressure_force / (self.density + 1e-3);
-
-        let viscosity_force = self.calculate_viscosity_force(other_particles);
+        let viscosity_force =
+            self.calculate_viscosity_force(other_particles.clone(), config, kernels);
         acceleration += viscosity_force;
 
-        let cursor_force = self.calculate_cursor_force(cursor);
+        if config.surface_tension != 0.0 {
+            let surface_tension_force =
+                self.calculate_surface_tension_force(other_particles, config);
+            acceleration += surface_tension_force / self.mass;
+        }
+
+        let cursor_force = self.calculate_cursor_force(cursor, config, rng);
         acceleration += cursor_force / (self.density + 1e-3);
 
         acceleration
     }
 
-    fn next_position(&self, acceleration: Vector, delta_time: f32) -> Vector {
-        let final_velocity = self.velocity + acceleration * delta_time;
-        let displacement = final_velocity * delta_time;
-        let new_position = self.position + displacement;
-        return new_position;
-    }
-
-    fn calculate_cursor_force(&self, cursor: Cursor) -> Vector {
+    fn calculate_cursor_force(
+        &self,
+        cursor: Cursor,
+        config: &SimulationConfig,
+        rng: &mut impl Rng,
+    ) -> Vector {
         if cursor.force_type == CursorForceType::None {
             return Vector::zero();
         }
         let offset = cursor.position - self.position;
         let dst = offset.magnitude();
         let dir = if dst == 0.0 {
-            random_direction()
+            random_direction(rng)
         } else {
             offset / dst
         };
         let mut force = Vector::zero();
-        if dst < cursor.radius as f32 {
+        if dst < cursor.radius {
             match cursor.force_type {
                 CursorForceType::Attract => {
                     force = dir;
@@ -132,62 +313,191 @@ impl Particle {
                 _ => {}
             }
         }
-        return force * constants::CURSOR_CONSTANT;
+        force * config.cursor_constant
     }
 
-    fn calculate_pressure_force(&self, other_particles: &Vec<Particle>) -> Vector {
+    fn calculate_pressure_force<'a>(
+        &self,
+        other_particles: impl Iterator<Item = &'a Particle>,
+        config: &SimulationConfig,
+        kernels: &Kernels,
+        rng: &mut impl Rng,
+    ) -> Vector {
         let mut pressure_force: Vector = Vector::zero();
 
         for other in other_particles {
-            if self == other {
-                continue;
-            }
+            let offset = other.predicted_position - self.predicted_position;
+            let dst = offset.magnitude();
+            let dir = if dst == 0.0 {
+                random_direction(rng)
+            } else {
+                offset / dst
+            };
 
-            let offset = other.position - self.position;
+            // The gradient is negative inside the support, so positive pressure
+            // pushes the particle away from its neighbour.
+            let influence = kernels.density.gradient(dst);
+            let pressure = calculate_shared_pressure(self, other, config);
+            pressure_force += dir * pressure * influence;
+        }
+        pressure_force
+    }
+
+    /// Push of the boundary samples, each mirroring the particle's own pressure
+    /// and weighted by its volume. Only compression is mirrored, so the wall
+    /// never pulls on the fluid. Near pressure is not mirrored.
+    fn calculate_boundary_pressure_force<'a>(
+        &self,
+        boundary_samples: impl Iterator<Item = &'a BoundaryParticle>,
+        config: &SimulationConfig,
+        kernels: &Kernels,
+    ) -> Vector {
+        let pressure = density_to_pressure(self, config).max(0.0);
+        let mut pressure_force = Vector::zero();
+        for sample in boundary_samples {
+            let gradient = kernel_gradient(kernels, self.predicted_position - sample.position);
+            pressure_force -= gradient * (pressure * sample.volume);
+        }
+        pressure_force
+    }
+
+    /// `boundary_pressure` matching [`Self::calculate_acceleration`].
+    pub fn explicit_boundary_pressure(&self, config: &SimulationConfig) -> f32 {
+        density_to_pressure(self, config).max(0.0) * self.mass / (self.density + 1e-3)
+    }
+
+    fn calculate_double_density_force<'a>(
+        &self,
+        other_particles: impl Iterator<Item = &'a Particle>,
+        config: &SimulationConfig,
+        kernels: &Kernels,
+        rng: &mut impl Rng,
+    ) -> Vector {
+        let pressure = density_to_pressure(self, config);
+        let near_pressure = near_density_to_pressure(self.near_density, config);
+        let mut pressure_force = Vector::zero();
+
+        for other in other_particles {
+            let offset = other.predicted_position - self.predicted_position;
             let dst = offset.magnitude();
             let dir = if dst == 0.0 {
-                random_direction()
+                random_direction(rng)
             } else {
                 offset / dst
             };
 
-            let influence = smoothing_kernel_derivative(dst, self.get_smoothing_radius());
-            let pressure = calculate_shared_pressure(self.density, other.density);
-            pressure_force -= dir * pressure * influence;
+            let shared_pressure = (pressure + density_to_pressure(other, config)) / 2.0;
+            let shared_near_pressure =
+                (near_pressure + near_density_to_pressure(other.near_density, config)) / 2.0;
+            // Both gradients are negative inside the support, so positive
+            // pressure pushes the particle away from its neighbour.
+            pressure_force += dir * shared_pressure * kernels.density.gradient(dst);
+            pressure_force += dir * shared_near_pressure * kernels.near_density.gradient(dst);
         }
-        return pressure_force;
+        pressure_force
     }
 
-    pub fn calculate_viscosity_force(&self, other_particles: &Vec<Particle>) -> Vector {
-        let mut viscosity_force: Vector = Vector::zero();
+    /// Akinci et al. surface tension: cohesion pulls neighbours towards a preferred
+    /// spacing and the curvature term flattens the surface. Both are scaled up where
+    /// the neighbourhood is thinner than rest density, i.e. at the surface.
+    fn calculate_surface_tension_force<'a>(
+        &self,
+        other_particles: impl Iterator<Item = &'a Particle>,
+        config: &SimulationConfig,
+    ) -> Vector {
+        let gamma = config.surface_tension;
+        let radius = config.smoothing_radius as f32;
+        let rest_density = self.rest_density(config);
+        let mut force = Vector::zero();
 
         for other in other_particles {
-            if self == other {
+            let offset = self.predicted_position - other.predicted_position;
+            let dst = offset.magnitude();
+            if dst == 0.0 {
                 continue;
             }
 
-            let offset = self.position - other.position;
+            let cohesion =
+                offset / dst * -gamma * self.mass * other.mass * cohesion_spline(dst, radius);
+            let curvature = (self.surface_normal - other.surface_normal) * -gamma * self.mass;
+            let correction =
+                (rest_density + other.rest_density(config)) / (self.density + other.density + 1e-3);
+            force += (cohesion + curvature) * correction;
+        }
+        force
+    }
+
+    /// Scaled colour-field gradient, `h Σ m/ρ ∇W`. Long at the free surface and
+    /// close to zero inside the fluid; feeds the curvature term of surface tension.
+    pub fn calculate_surface_normal<'a>(
+        &self,
+        other_particles: impl Iterator<Item = &'a Particle>,
+        config: &SimulationConfig,
+        kernels: &Kernels,
+    ) -> Vector {
+        let mut normal = Vector::zero();
+        for other in other_particles {
+            let offset = self.predicted_position - other.predicted_position;
+            let dst = offset.magnitude();
+            if dst == 0.0 || other.density == 0.0 {
+                continue;
+            }
+            normal += offset / dst * kernels.density.gradient(dst) * other.mass / other.density;
+        }
+        normal * config.smoothing_radius as f32
+    }
+
+    pub fn calculate_viscosity_force<'a>(
+        &self,
+        other_particles: impl Iterator<Item = &'a Particle>,
+        config: &SimulationConfig,
+        kernels: &Kernels,
+    ) -> Vector {
+        let mut viscosity_force: Vector = Vector::zero();
+
+        for other in other_particles {
+            let offset = self.predicted_position - other.predicted_position;
             let dst = offset.magnitude();
-            let influence = viscosity_smoothing_kernel(dst, self.get_smoothing_radius());
+            let influence = kernels.viscosity.value(dst);
             viscosity_force += (other.velocity - self.velocity) * influence;
         }
-        viscosity_force * constants::VISCOSITY_CONSTANT
+        viscosity_force * config.material(self.material).viscosity
     }
 
-    pub fn calculate_density(&self, point: Vector, other_particles: &Vec<Particle>) -> f32 {
-        let mut density = 0.0;
+    /// Own mass times the number density of the neighbourhood (Solenthaler &
+    /// Pajarola 2008), so a light particle surrounded by a heavier fluid is not
+    /// read as compressed. The solvers' pressure terms are built on `m_i / ρ_i`
+    /// as the particle volume to match. Boundary samples count with their volume.
+    pub fn calculate_density<'a>(
+        &self,
+        point: Vector,
+        other_particles: impl Iterator<Item = &'a Particle>,
+        boundary_samples: impl Iterator<Item = &'a BoundaryParticle>,
+        kernels: &Kernels,
+    ) -> f32 {
+        let mut number_density = 0.0;
         for p in other_particles {
-            if self == p {
-                continue;
-            }
-            let dst = (p.position - point).magnitude();
-            let influence = smoothing_kernel(dst, p.get_smoothing_radius());
-            density += Particle::MASS * influence;
+            let dst = (p.predicted_position - point).magnitude();
+            number_density += kernels.density.value(dst);
+        }
+        for sample in boundary_samples {
+            let dst = (sample.position - point).magnitude();
+            number_density += kernels.density.value(dst) * sample.volume;
         }
-        return density;
+        self.mass * number_density
     }
 
-    pub fn get_smoothing_radius(&self) -> f32 {
-        Particle::SMOOTHING_RADIUS as f32
+    pub fn calculate_near_density<'a>(
+        &self,
+        point: Vector,
+        other_particles: impl Iterator<Item = &'a Particle>,
+        kernels: &Kernels,
+    ) -> f32 {
+        let mut near_number_density = 0.0;
+        for p in other_particles {
+            let dst = (p.predicted_position - point).magnitude();
+            near_number_density += kernels.near_density.value(dst);
+        }
+        self.mass * near_number_density
     }
 }
@@ -1,122 +1,381 @@
-use rand::seq::index;
+use super::{particle::Particle, vector::Vector};
 
-use crate::constants;
+/// Anything a [`ParticlesLookup`] can bucket.
+pub trait LookupPosition: Clone {
+    /// Where the entry is filed in the grid and measured from by queries.
+    fn lookup_position(&self) -> Vector;
+    /// Moves every position of the entry by `offset`, used to place the ghost
+    /// copies across a periodic seam.
+    fn translate(&mut self, offset: Vector);
+}
+
+impl LookupPosition for Particle {
+    fn lookup_position(&self) -> Vector {
+        self.predicted_position
+    }
 
-use super::{
-    particle::{self, Particle},
-    vector::Vector,
-};
+    fn translate(&mut self, offset: Vector) {
+        self.position += offset;
+        self.predicted_position += offset;
+    }
+}
 
+/// Uniform grid over the domain used for neighbour searches.
+///
+/// Particles are bucketed by their predicted position with a counting sort:
+/// `cell_entries` holds particle indices grouped by cell and `cell_start[c]..cell_start[c + 1]`
+/// is the slice belonging to cell `c`. All buffers are reused between rebuilds, so
+/// neither `update_cells` nor a query allocates once the particle count is stable.
+///
+/// Along a periodic axis, entries within one cell of either end also get a ghost
+/// copy shifted across the seam. Ghosts are filed after the particles, with
+/// indices from `particles.len()` on, and queries report them under the index
+/// of the particle they copy.
 #[derive(Debug, Clone, PartialEq)]
-pub struct ParticlesLookup {
-    pub particles: Vec<Particle>,
-    cells: Vec<Vec<Vec<usize>>>,
+pub struct ParticlesLookup<T = Particle> {
+    pub particles: Vec<T>,
     cell_size: f32,
+    dimensions: (usize, usize),
+    /// Length after which the domain repeats along x and y, if it does.
+    period: (Option<f32>, Option<f32>),
+    ghosts: Vec<T>,
+    /// Index of the particle each ghost copies and the offset it was moved by.
+    ghost_sources: Vec<(usize, Vector)>,
+    cell_start: Vec<usize>,
+    cell_entries: Vec<usize>,
+    particle_cells: Vec<usize>,
 }
 
-impl ParticlesLookup {
+impl<T: LookupPosition> ParticlesLookup<T> {
     pub fn new(
-        particles: Vec<Particle>,
+        particles: Vec<T>,
         cell_size: f32,
         dimensions: (usize, usize),
-    ) -> ParticlesLookup {
-        let cells = vec![vec![Vec::new(); dimensions.1]; dimensions.0];
+    ) -> ParticlesLookup<T> {
+        let dimensions = (dimensions.0.max(1), dimensions.1.max(1));
         ParticlesLookup {
             particles,
-            cells,
             cell_size,
+            dimensions,
+            period: (None, None),
+            ghosts: Vec::new(),
+            ghost_sources: Vec::new(),
+            cell_start: vec![0; dimensions.0 * dimensions.1 + 1],
+            cell_entries: Vec::new(),
+            particle_cells: Vec::new(),
         }
     }
 
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    /// Makes the domain wrap around after `period` along the axes that have one.
+    /// Takes effect with the next `update_cells`.
+    pub fn set_period(&mut self, period: (Option<f32>, Option<f32>)) {
+        self.period = period;
+    }
+
+    fn cell_coords(&self, position: Vector) -> (usize, usize) {
+        let cell = position / self.cell_size;
+        let x = cell.x.clamp(0.0, (self.dimensions.0 - 1) as f32) as usize;
+        let y = cell.y.clamp(0.0, (self.dimensions.1 - 1) as f32) as usize;
+        (x, y)
+    }
+
+    fn cell_index(&self, (x, y): (usize, usize)) -> usize {
+        x * self.dimensions.1 + y
+    }
+
     pub fn update_cells(&mut self) {
-        for row in &mut self.cells {
-            for col in row {
-                col.clear();
-            }
+        self.create_ghosts();
+
+        let cell_count = self.dimensions.0 * self.dimensions.1;
+        self.cell_start.clear();
+        self.cell_start.resize(cell_count + 1, 0);
+
+        self.particle_cells.clear();
+        for particle in self.particles.iter().chain(&self.ghosts) {
+            let cell = self.cell_index(self.cell_coords(particle.lookup_position()));
+            self.particle_cells.push(cell);
+            self.cell_start[cell] += 1;
         }
 
-        for (index, particle) in self.particles.iter().enumerate() {
-            let cell = particle.position / self.cell_size;
-            let x = cell.x.clamp(0.0, (self.cells.len() - 1) as f32) as usize;
-            let y = cell.y.clamp(0.0, (self.cells[0].len() - 1) as f32) as usize;
-            self.cells[x][y].push(index);
+        // Turn counts into exclusive end offsets, then walk the particles backwards
+        // so every cell ends up holding its indices in ascending order.
+        let mut end = 0;
+        for start in self.cell_start.iter_mut() {
+            end += *start;
+            *start = end;
+        }
+
+        self.cell_entries.clear();
+        self.cell_entries.resize(self.particle_cells.len(), 0);
+        for (index, &cell) in self.particle_cells.iter().enumerate().rev() {
+            self.cell_start[cell] -= 1;
+            self.cell_entries[self.cell_start[cell]] = index;
         }
     }
 
-    pub fn query_radius(
-        &mut self,
-        point: Option<Vector>,
-        radius: Option<f32>,
-        current_index: Option<usize>,
-    ) -> (Vec<Particle>, Option<&mut Particle>) {
-        let mut neighbors: Vec<Particle> = Vec::new();
-        let mut current_particle: Option<&mut Particle> = None;
-        let mut point = point;
-        let mut radius = radius;
-        let particles_clone = self.particles.clone();
-
-        if let Some(current_index) = current_index {
-            if let Some(particle) = self.particles.get_mut(current_index) {
-                let particle_clone = particle.clone();
-                current_particle = Some(particle);
-                if point.is_none() {
-                    point = Some(particle_clone.position);
-                }
-                if radius.is_none() {
-                    radius = Some(particle_clone.get_smoothing_radius());
-                }
-            }
+    /// Copies every particle within one cell of a periodic seam to the other
+    /// side, and those near two seams to the opposite corner as well.
+    fn create_ghosts(&mut self) {
+        self.ghosts.clear();
+        self.ghost_sources.clear();
+        if self.period == (None, None) {
+            return;
         }
 
-        let point = point.expect("Point must be set");
-        let radius = radius.expect("Radius must be set");
-
-        let min = ((point - Vector::new(radius, radius)) / self.cell_size).max(0.0);
-        let mut max = (point + Vector::new(radius, radius)) / self.cell_size;
-        max.x = max.x.min((self.cells.len() - 1) as f32);
-        max.y = max.y.min((self.cells[0].len() - 1) as f32);
-
-        for x in (min.x as usize)..=(max.x as usize) {
-            for y in (min.y as usize)..=(max.y as usize) {
-                for &index in &self.cells[x][y] {
-                    if let Some(current_index) = current_index {
-                        if index == current_index {
-                            continue;
-                        }
-                    }
-                    neighbors.push(particles_clone[index].clone());
-                }
+        let reach = self.cell_size;
+        let shift = |coordinate: f32, period: Option<f32>| match period {
+            Some(length) if coordinate < reach => length,
+            Some(length) if coordinate > length - reach => -length,
+            _ => 0.0,
+        };
+        for (index, particle) in self.particles.iter().enumerate() {
+            let position = particle.lookup_position();
+            let x = shift(position.x, self.period.0);
+            let y = shift(position.y, self.period.1);
+            let offsets = [
+                (x != 0.0, Vector::new(x, 0.0)),
+                (y != 0.0, Vector::new(0.0, y)),
+                (x != 0.0 && y != 0.0, Vector::new(x, y)),
+            ];
+            for (_, offset) in offsets.into_iter().filter(|&(needed, _)| needed) {
+                let mut ghost = particle.clone();
+                ghost.translate(offset);
+                self.ghosts.push(ghost);
+                self.ghost_sources.push((index, offset));
             }
         }
+    }
+
+    /// Refreshes the ghosts from their particles without moving them to other
+    /// cells. Needed after the particles changed in place between
+    /// `update_cells` and the next query that should see the change.
+    pub fn sync_ghosts(&mut self) {
+        for (ghost, &(index, offset)) in self.ghosts.iter_mut().zip(&self.ghost_sources) {
+            *ghost = self.particles[index].clone();
+            ghost.translate(offset);
+        }
+    }
+
+    /// Appends `particles` and files them, so queries find them right away.
+    pub fn extend(&mut self, particles: impl IntoIterator<Item = T>) {
+        self.particles.extend(particles);
+        self.update_cells();
+    }
+
+    /// Keeps only the particles for which `keep` returns true and refiles the
+    /// rest. Later particles move down to fill the gaps, keeping their order.
+    /// Returns how many were removed.
+    pub fn retain(&mut self, keep: impl FnMut(&T) -> bool) -> usize {
+        let count = self.particles.len();
+        self.particles.retain(keep);
+        let removed = count - self.particles.len();
+        if removed > 0 {
+            self.update_cells();
+        }
+        removed
+    }
+
+    /// Removes particle `index` like [`Vec::remove`] and refiles the rest.
+    pub fn remove(&mut self, index: usize) -> T {
+        let particle = self.particles.remove(index);
+        self.update_cells();
+        particle
+    }
+
+    /// The particle or ghost filed under `entry`, with the index it is reported as.
+    fn entry(&self, entry: usize) -> (usize, &T) {
+        match entry.checked_sub(self.particles.len()) {
+            Some(ghost) => (self.ghost_sources[ghost].0, &self.ghosts[ghost]),
+            None => (entry, &self.particles[entry]),
+        }
+    }
+
+    /// Iterates all particles whose predicted position lies within `radius` of `point`.
+    pub fn query_radius(&self, point: Vector, radius: f32) -> Neighbors<'_, T> {
+        Neighbors::new(self, point, radius, None)
+    }
+
+    /// Iterates the entries within one cell size of `point`.
+    pub fn query_around(&self, point: Vector) -> Neighbors<'_, T> {
+        Neighbors::new(self, point, self.cell_size, None)
+    }
+
+    /// Iterates the neighbours of particle `index` within one cell size, excluding
+    /// the particle itself.
+    pub fn query_neighbors(&self, index: usize) -> Neighbors<'_, T> {
+        let point = self.particles[index].lookup_position();
+        Neighbors::new(self, point, self.cell_size, Some(index))
+    }
+}
+
+/// Allocation-free iterator over the particles found by a [`ParticlesLookup`] query,
+/// yielding each particle together with its index.
+#[derive(Debug, Clone)]
+pub struct Neighbors<'a, T = Particle> {
+    lookup: &'a ParticlesLookup<T>,
+    point: Vector,
+    radius_sq: f32,
+    exclude: Option<usize>,
+    min: (usize, usize),
+    max: (usize, usize),
+    cell: (usize, usize),
+    entry: usize,
+    entry_end: usize,
+}
+
+impl<'a, T: LookupPosition> Neighbors<'a, T> {
+    fn new(
+        lookup: &'a ParticlesLookup<T>,
+        point: Vector,
+        radius: f32,
+        exclude: Option<usize>,
+    ) -> Neighbors<'a, T> {
+        let extent = Vector::new(radius, radius);
+        let min = lookup.cell_coords(point - extent);
+        let max = lookup.cell_coords(point + extent);
+        let mut neighbors = Neighbors {
+            lookup,
+            point,
+            radius_sq: radius * radius,
+            exclude,
+            min,
+            max,
+            cell: min,
+            entry: 0,
+            entry_end: 0,
+        };
+        neighbors.enter_cell();
+        neighbors
+    }
+
+    fn enter_cell(&mut self) {
+        let cell = self.lookup.cell_index(self.cell);
+        self.entry = self.lookup.cell_start[cell];
+        self.entry_end = self.lookup.cell_start[cell + 1];
+    }
+
+    fn next_cell(&mut self) -> bool {
+        if self.cell.1 < self.max.1 {
+            self.cell.1 += 1;
+        } else if self.cell.0 < self.max.0 {
+            self.cell.0 += 1;
+            self.cell.1 = self.min.1;
+        } else {
+            return false;
+        }
+        self.enter_cell();
+        true
+    }
 
-        (neighbors, current_particle)
+    /// Drops the indices and yields only the particles.
+    pub fn particles(self) -> impl Iterator<Item = &'a T> + Clone {
+        self.map(|(_, particle)| particle)
     }
+}
 
-    pub fn query_all(
-        &mut self,
-        current_index: Option<usize>,
-    ) -> (Vec<Particle>, Option<&mut Particle>) {
-        let mut particles = Vec::new();
-        let mut current_particle = None;
+impl<'a, T: LookupPosition> Iterator for Neighbors<'a, T> {
+    type Item = (usize, &'a T);
 
-        for (index, particle) in self.particles.iter_mut().enumerate() {
-            if let Some(current_index) = current_index {
-                if index == current_index {
-                    current_particle = Some(particle);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while self.entry < self.entry_end {
+                let (index, particle) = self.lookup.entry(self.lookup.cell_entries[self.entry]);
+                self.entry += 1;
+                if Some(index) == self.exclude {
                     continue;
                 }
+                let offset = particle.lookup_position() - self.point;
+                if offset.dot(offset) <= self.radius_sq {
+                    return Some((index, particle));
+                }
+            }
+            if !self.next_cell() {
+                return None;
             }
-            particles.push(particle.clone());
         }
-
-        (particles, current_particle)
     }
+}
 
-    pub fn copy(&self) -> ParticlesLookup {
-        ParticlesLookup {
-            particles: self.particles.clone(),
-            cells: self.cells.clone(),
-            cell_size: self.cell_size,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Point(Vector);
+
+    impl LookupPosition for Point {
+        fn lookup_position(&self) -> Vector {
+            self.0
+        }
+
+        fn translate(&mut self, offset: Vector) {
+            self.0 += offset;
         }
     }
+
+    /// A 100 by 100 domain of 10 unit cells, periodic along both axes.
+    fn periodic(points: &[(f32, f32)]) -> ParticlesLookup<Point> {
+        let points = points.iter().map(|&point| Point(point.into())).collect();
+        let mut lookup = ParticlesLookup::new(points, 10.0, (10, 10));
+        lookup.set_period((Some(100.0), Some(100.0)));
+        lookup.update_cells();
+        lookup
+    }
+
+    fn neighbors(lookup: &ParticlesLookup<Point>, index: usize) -> Vec<(usize, Vector)> {
+        lookup
+            .query_neighbors(index)
+            .map(|(index, point)| (index, point.0))
+            .collect()
+    }
+
+    #[test]
+    fn neighbours_are_found_across_a_seam() {
+        let mut lookup = periodic(&[(2.0, 50.0), (97.0, 50.0)]);
+        assert_eq!(neighbors(&lookup, 0), vec![(1, Vector::new(-3.0, 50.0))]);
+        assert_eq!(neighbors(&lookup, 1), vec![(0, Vector::new(102.0, 50.0))]);
+
+        lookup.set_period((None, None));
+        lookup.update_cells();
+        assert!(neighbors(&lookup, 0).is_empty());
+    }
+
+    #[test]
+    fn neighbours_are_found_across_a_corner() {
+        let lookup = periodic(&[(2.0, 2.0), (98.0, 98.0), (98.0, 2.0), (2.0, 98.0)]);
+        let mut found = neighbors(&lookup, 0);
+        found.sort_by_key(|&(index, _)| index);
+        assert_eq!(
+            found,
+            vec![
+                (1, Vector::new(-2.0, -2.0)),
+                (2, Vector::new(-2.0, 2.0)),
+                (3, Vector::new(2.0, -2.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn sync_ghosts_follows_particles_moved_in_place() {
+        let mut lookup = periodic(&[(2.0, 50.0), (97.0, 50.0)]);
+        lookup.particles[1].translate(Vector::new(1.0, 0.0));
+        assert_eq!(neighbors(&lookup, 0), vec![(1, Vector::new(-3.0, 50.0))]);
+
+        lookup.sync_ghosts();
+        assert_eq!(neighbors(&lookup, 0), vec![(1, Vector::new(-2.0, 50.0))]);
+    }
+
+    #[test]
+    fn query_neighbors_skips_the_particles_own_ghost() {
+        // The period is one cell, so the ghost lies within the query radius.
+        let mut lookup = ParticlesLookup::new(vec![Point(Vector::new(2.0, 5.0))], 10.0, (1, 1));
+        lookup.set_period((Some(10.0), None));
+        lookup.update_cells();
+        assert_eq!(lookup.query_around(Vector::new(2.0, 5.0)).count(), 2);
+        assert_eq!(lookup.query_neighbors(0).count(), 0);
+    }
 }
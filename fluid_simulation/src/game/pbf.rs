@@ -0,0 +1,227 @@
+use crate::config::SimulationConfig;
+
+use super::{
+    boundary::boundary_gradient,
+    solver::{
+        kernel_gradient, non_pressure_accelerations, prototype_gradient_sums, DensityPass, Solver,
+        SolverStats, SolverStep,
+    },
+    vector::Vector,
+    walls::WallMode,
+};
+
+/// Position Based Fluids (Macklin & Müller 2013).
+///
+/// Density constraints `ρ_i / ρ₀ - 1 = 0` are projected directly onto
+/// `predicted_position`, so the step stays stable for any timestep. Velocities
+/// are derived from the corrected positions and smoothed with XSPH. Corrections
+/// are weighted by inverse mass, so a lighter material gives way to a heavier one.
+#[derive(Debug, Default)]
+pub struct Pbf {
+    density_pass: DensityPass,
+    non_pressure: Vec<Vector>,
+    densities: Vec<f32>,
+    lambdas: Vec<f32>,
+    lambda_sums: Vec<f32>,
+    corrections: Vec<Vector>,
+    velocities: Vec<Vector>,
+}
+
+impl Pbf {
+    pub const MIN_ITERATIONS: u32 = 2;
+    /// Constraint force mixing, relative to the gradient norm of a full neighbourhood.
+    pub const RELAXATION: f32 = 1.0;
+    /// Artificial pressure `-k (W(r) / W(Δq))ⁿ` against tensile instability.
+    pub const TENSILE_STRENGTH: f32 = 0.1;
+    pub const TENSILE_EXPONENT: i32 = 4;
+    /// `Δq` as a fraction of the smoothing radius.
+    pub const TENSILE_DISTANCE: f32 = 0.2;
+
+    pub fn new() -> Pbf {
+        Pbf::default()
+    }
+
+    /// Keeps `position` off the solid walls; periodic and open sides are
+    /// handled once the particle moved.
+    fn clamp_to_window(position: Vector, config: &SimulationConfig) -> Vector {
+        let radius = config.particle_radius as f32;
+        let walls = &config.walls;
+        let clamp = |coordinate: f32, (low, high): (WallMode, WallMode), length: u32| {
+            let min = if low.is_solid() { radius } else { f32::MIN };
+            let max = if high.is_solid() {
+                length as f32 - radius
+            } else {
+                f32::MAX
+            };
+            coordinate.clamp(min, max)
+        };
+        Vector::new(
+            clamp(position.x, (walls.left, walls.right), config.window_size.0),
+            clamp(position.y, (walls.top, walls.bottom), config.window_size.1),
+        )
+    }
+}
+
+impl Solver for Pbf {
+    fn name(&self) -> &'static str {
+        "pbf"
+    }
+
+    fn step(&mut self, mut step: SolverStep<'_>) -> SolverStats {
+        self.density_pass.run(&mut step);
+        let SolverStep {
+            lookup,
+            boundary,
+            executor,
+            config,
+            kernels,
+            cursor,
+            delta_time,
+            step_seed,
+        } = step;
+        let particle_amt = lookup.particles.len();
+
+        non_pressure_accelerations(
+            &mut self.non_pressure,
+            lookup,
+            executor,
+            config,
+            kernels,
+            cursor,
+            step_seed,
+        );
+        let non_pressure = &self.non_pressure;
+        executor.for_each_mut(&mut lookup.particles, |i, particle| {
+            let velocity = particle.velocity + non_pressure[i] * delta_time;
+            particle.predicted_position =
+                Self::clamp_to_window(particle.position + velocity * delta_time, config);
+        });
+        lookup.update_cells();
+
+        let (gradient_sum, gradient_dot_sum) = prototype_gradient_sums(config, kernels);
+        let relaxation = Self::RELAXATION * (gradient_sum.dot(gradient_sum) + gradient_dot_sum);
+        let tensile_reference = kernels
+            .density
+            .value(Self::TENSILE_DISTANCE * kernels.density.support_radius());
+
+        self.lambda_sums.clear();
+        self.lambda_sums.resize(particle_amt, 0.0);
+
+        let mut stats = SolverStats::default();
+        while stats.iterations < config.max_iterations {
+            let snapshot = &*lookup;
+            executor.map_into(&mut self.densities, particle_amt, |i| {
+                let particle = &snapshot.particles[i];
+                let point = particle.predicted_position;
+                let other_particles = snapshot.query_neighbors(i).particles();
+                let boundary_samples = boundary.query_around(point).particles();
+                particle.calculate_density(point, other_particles, boundary_samples, kernels)
+            });
+
+            stats.iterations += 1;
+            stats.density_error = SolverStats::average_density_error(
+                self.densities.iter().copied(),
+                &lookup.particles,
+                config,
+            );
+            if stats.iterations > Self::MIN_ITERATIONS
+                && stats.density_error <= config.density_error_tolerance
+            {
+                break;
+            }
+
+            let densities = &self.densities;
+            executor.map_into(&mut self.lambdas, particle_amt, |i| {
+                // Only compressed particles are pushed apart; the artificial
+                // pressure below handles clustering at the free surface.
+                let particle = &snapshot.particles[i];
+                let rest_density = particle.rest_density(config);
+                let constraint = (densities[i] / rest_density - 1.0).max(0.0);
+                if constraint == 0.0 {
+                    return 0.0;
+                }
+                let mut gradient_sum = Vector::zero();
+                let mut gradient_dot_sum = 0.0;
+                for other in snapshot.query_neighbors(i).particles() {
+                    let gradient = kernel_gradient(
+                        kernels,
+                        particle.predicted_position - other.predicted_position,
+                    ) * (particle.mass / rest_density);
+                    gradient_sum += gradient;
+                    gradient_dot_sum += gradient.dot(gradient) / other.mass;
+                }
+                // Boundary samples do not move, so they only add to the gradient.
+                gradient_sum += boundary_gradient(boundary, particle.predicted_position, kernels)
+                    * (particle.mass / rest_density);
+                let relaxation = relaxation * particle.mass / (rest_density * rest_density);
+                -constraint
+                    / (gradient_sum.dot(gradient_sum) / particle.mass
+                        + gradient_dot_sum
+                        + relaxation)
+            });
+
+            let lambdas = &self.lambdas;
+            executor.for_each_mut(&mut self.lambda_sums, |i, sum| *sum += lambdas[i]);
+            executor.map_into(&mut self.corrections, particle_amt, |i| {
+                let particle = &snapshot.particles[i];
+                let own = 1.0 / particle.rest_density(config);
+                let mut correction = Vector::zero();
+                for (j, other) in snapshot.query_neighbors(i) {
+                    let offset = particle.predicted_position - other.predicted_position;
+                    let tensile = if tensile_reference > 0.0 {
+                        let ratio = kernels.density.value(offset.magnitude()) / tensile_reference;
+                        -Self::TENSILE_STRENGTH * ratio.powi(Self::TENSILE_EXPONENT)
+                    } else {
+                        0.0
+                    };
+                    let shared = (lambdas[i] + tensile) * own
+                        + lambdas[j] * other.mass / (particle.mass * other.rest_density(config));
+                    correction += kernel_gradient(kernels, offset) * shared;
+                }
+                let boundary_gradient =
+                    boundary_gradient(boundary, particle.predicted_position, kernels);
+                correction + boundary_gradient * (lambdas[i] * own)
+            });
+
+            let corrections = &self.corrections;
+            executor.for_each_mut(&mut lookup.particles, |i, particle| {
+                particle.predicted_position =
+                    Self::clamp_to_window(particle.predicted_position + corrections[i], config);
+            });
+            lookup.sync_ghosts();
+        }
+
+        let snapshot = &*lookup;
+        executor.map_into(&mut self.velocities, particle_amt, |i| {
+            let particle = &snapshot.particles[i];
+            (particle.predicted_position - particle.position) / delta_time
+        });
+
+        let velocities = &self.velocities;
+        let densities = &self.densities;
+        executor.map_into(&mut self.corrections, particle_amt, |i| {
+            let particle = &snapshot.particles[i];
+            let mut smoothing = Vector::zero();
+            for (j, other) in snapshot.query_neighbors(i) {
+                let dst = (particle.predicted_position - other.predicted_position).magnitude();
+                let weight = other.mass / densities[j].max(other.rest_density(config));
+                smoothing += (velocities[j] - velocities[i]) * kernels.density.value(dst) * weight;
+            }
+            velocities[i] + smoothing * config.xsph_viscosity
+        });
+
+        let corrected = &self.corrections;
+        let lambda_sums = &self.lambda_sums;
+        executor.for_each_mut(&mut lookup.particles, |i, particle| {
+            particle.density = densities[i];
+            // The boundary moved the particle by `Σλ / ρ₀ Σ_b V_b ∇W_ib` in
+            // total, i.e. pushed it with that over `Δt²`.
+            particle.boundary_pressure = -lambda_sums[i] * particle.mass
+                / (particle.rest_density(config) * delta_time * delta_time);
+            let acceleration = (corrected[i] - particle.velocity) / delta_time;
+            particle.integrate(acceleration, delta_time, config)
+        });
+
+        stats
+    }
+}
@@ -0,0 +1,335 @@
+use std::f32::consts::PI;
+
+use serde::Deserialize;
+
+use crate::config::SimulationConfig;
+
+use super::boundary::{sample_volumes, BoundaryParticle};
+use super::kernel::Kernels;
+use super::obstacle::polygon_distance;
+use super::particle::Particle;
+use super::vector::Vector;
+
+/// Outline of a [`RigidBody`] in body space.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BodyShape {
+    Circle {
+        radius: f32,
+    },
+    /// Convex polygon in either winding order. In the config the points are
+    /// relative to `position`; the built body keeps them around its centre of mass.
+    Polygon {
+        points: Vec<(f32, f32)>,
+    },
+}
+
+impl BodyShape {
+    /// Area, centroid and polar second moment of area about the centroid.
+    fn area_properties(&self) -> (f32, Vector, f32) {
+        match self {
+            BodyShape::Circle { radius } => {
+                let area = PI * radius * radius;
+                (area, Vector::zero(), 0.5 * area * radius * radius)
+            }
+            BodyShape::Polygon { points } => {
+                let mut area = 0.0;
+                let mut centroid = Vector::zero();
+                let mut moment = 0.0;
+                for (i, &end) in points.iter().enumerate() {
+                    let start = Vector::from(points[(i + points.len() - 1) % points.len()]);
+                    let end = Vector::from(end);
+                    let cross = start.cross(end);
+                    area += cross / 2.0;
+                    centroid += (start + end) * cross / 6.0;
+                    moment += cross * (start.dot(start) + start.dot(end) + end.dot(end)) / 12.0;
+                }
+                let centroid = centroid / area;
+                // The sums carry the sign of the winding order; so does `area`.
+                let moment = moment - area * centroid.dot(centroid);
+                (area.abs(), centroid, moment.abs())
+            }
+        }
+    }
+
+    /// Points along the outline at most `spacing` apart.
+    fn outline(&self, spacing: f32) -> Vec<Vector> {
+        match self {
+            BodyShape::Circle { radius } => {
+                let count = (2.0 * PI * radius / spacing).ceil().max(3.0) as usize;
+                (0..count)
+                    .map(|k| Vector::new(*radius, 0.0).rotate(2.0 * PI * k as f32 / count as f32))
+                    .collect()
+            }
+            BodyShape::Polygon { points } => {
+                let mut outline = Vec::new();
+                for (i, &end) in points.iter().enumerate() {
+                    let start = Vector::from(points[(i + points.len() - 1) % points.len()]);
+                    let edge = Vector::from(end) - start;
+                    let count = (edge.magnitude() / spacing).ceil().max(1.0) as usize;
+                    outline.extend((0..count).map(|k| start + edge * (k as f32 / count as f32)));
+                }
+                outline
+            }
+        }
+    }
+}
+
+/// Whether the closed polygon through `points` turns the same way at every
+/// vertex and encloses some area.
+pub fn is_convex(points: &[(f32, f32)]) -> bool {
+    let mut sign = 0.0;
+    for i in 0..points.len() {
+        let a = Vector::from(points[i]);
+        let b = Vector::from(points[(i + 1) % points.len()]);
+        let c = Vector::from(points[(i + 2) % points.len()]);
+        let turn = (b - a).cross(c - b);
+        if turn == 0.0 {
+            continue;
+        }
+        if sign * turn < 0.0 {
+            return false;
+        }
+        sign = turn.signum();
+    }
+    sign != 0.0
+}
+
+/// Initial state of a rigid body, as a `[[rigid_bodies]]` table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RigidBodyConfig {
+    pub shape: BodyShape,
+    pub position: (f32, f32),
+    /// Mass per square pixel. Particles of mass `m` packed at `particle_spacing`
+    /// `s` weigh `m / s²`, about 0.028 by default; lighter bodies float.
+    pub density: f32,
+    /// Rotation in radians, clockwise on screen.
+    #[serde(default)]
+    pub angle: f32,
+    #[serde(default)]
+    pub velocity: (f32, f32),
+    #[serde(default)]
+    pub angular_velocity: f32,
+}
+
+/// A dynamic circle or convex polygon coupled two ways with the fluid.
+///
+/// The outline is sampled into [`BoundaryParticle`]s that the solvers treat as
+/// solid, so the fluid is pushed out of the body and builds up pressure against
+/// it. The reaction, summed over the samples, is the fluid force and torque on
+/// the body. Particles that still end up inside are pushed out with an
+/// inelastic contact impulse. Bodies bounce off solid walls and obstacles but
+/// not off each other, wrap through periodic walls and fall out of open ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RigidBody {
+    /// Outline relative to the centre of mass, before rotation.
+    pub shape: BodyShape,
+    /// Centre of mass.
+    pub position: Vector,
+    pub angle: f32,
+    pub velocity: Vector,
+    pub angular_velocity: f32,
+    pub mass: f32,
+    /// Moment of inertia about the centre of mass.
+    pub inertia: f32,
+    /// Force the fluid exerted on the body during the most recent step.
+    pub fluid_force: Vector,
+    /// Torque about the centre of mass matching `fluid_force`.
+    pub fluid_torque: f32,
+    /// Outline samples in body space and their volumes.
+    samples: Vec<(Vector, f32)>,
+}
+
+impl RigidBody {
+    /// Share of the approach speed kept when bouncing off solid walls and
+    /// obstacles.
+    pub const RESTITUTION: f32 = 0.3;
+
+    /// Builds the body described by `config`, with boundary samples
+    /// `spacing` apart.
+    pub fn new(config: &RigidBodyConfig, spacing: f32, kernels: &Kernels) -> RigidBody {
+        let (area, centroid, moment) = config.shape.area_properties();
+        let shape = match &config.shape {
+            BodyShape::Circle { radius } => BodyShape::Circle { radius: *radius },
+            BodyShape::Polygon { points } => BodyShape::Polygon {
+                points: points
+                    .iter()
+                    .map(|&point| (Vector::from(point) - centroid).try_into().unwrap())
+                    .collect(),
+            },
+        };
+        let outline = shape.outline(spacing);
+        let volumes = sample_volumes(&outline, spacing, kernels);
+        RigidBody {
+            samples: outline.into_iter().zip(volumes).collect(),
+            shape,
+            position: Vector::from(config.position) + centroid.rotate(config.angle),
+            angle: config.angle,
+            velocity: Vector::from(config.velocity),
+            angular_velocity: config.angular_velocity,
+            mass: config.density * area,
+            inertia: config.density * moment,
+            fluid_force: Vector::zero(),
+            fluid_torque: 0.0,
+        }
+    }
+
+    /// Distance of the farthest outline point from the centre of mass.
+    pub fn bounding_radius(&self) -> f32 {
+        match &self.shape {
+            BodyShape::Circle { radius } => *radius,
+            BodyShape::Polygon { points } => points
+                .iter()
+                .map(|&point| Vector::from(point).magnitude())
+                .fold(0.0, f32::max),
+        }
+    }
+
+    /// Maps a point from body space to the window.
+    pub fn to_world(&self, point: Vector) -> Vector {
+        self.position + point.rotate(self.angle)
+    }
+
+    /// Velocity of the body material at `point`.
+    pub fn velocity_at(&self, point: Vector) -> Vector {
+        let arm = point - self.position;
+        self.velocity + Vector::new(-arm.y, arm.x) * self.angular_velocity
+    }
+
+    /// Like [`Obstacle::signed_distance`](super::obstacle::Obstacle::signed_distance)
+    /// for the body in its current pose.
+    pub fn signed_distance(&self, point: Vector) -> (f32, Vector) {
+        let local = (point - self.position).rotate(-self.angle);
+        let (distance, normal) = match &self.shape {
+            BodyShape::Circle { radius } => (local.magnitude() - radius, local.normalize()),
+            BodyShape::Polygon { points } => polygon_distance(points, local),
+        };
+        (distance, normal.rotate(self.angle))
+    }
+
+    /// The boundary samples in the current pose, tagged with `index` as their body.
+    pub fn boundary_particles(&self, index: usize) -> impl Iterator<Item = BoundaryParticle> + '_ {
+        self.samples.iter().map(move |&(point, volume)| {
+            let position = self.to_world(point);
+            BoundaryParticle {
+                position,
+                velocity: self.velocity_at(position),
+                volume,
+                body: Some(index),
+            }
+        })
+    }
+
+    pub fn apply_impulse(&mut self, impulse: Vector, point: Vector) {
+        self.velocity += impulse / self.mass;
+        self.angular_velocity += (point - self.position).cross(impulse) / self.inertia;
+    }
+
+    /// Inverse effective mass of the body along `normal` at `point`.
+    fn inverse_mass_along(&self, point: Vector, normal: Vector) -> f32 {
+        let arm = (point - self.position).cross(normal);
+        1.0 / self.mass + arm * arm / self.inertia
+    }
+
+    /// Accelerates the body by gravity and the fluid force and torque.
+    pub fn apply_forces(&mut self, delta_time: f32, config: &SimulationConfig) {
+        let acceleration = Vector::new(0.0, 1.0) * config.gravity + self.fluid_force / self.mass;
+        self.velocity += acceleration * delta_time;
+        self.angular_velocity += self.fluid_torque / self.inertia * delta_time;
+    }
+
+    /// Pushes the particles at `indices` out of the body and cancels their
+    /// approach velocity with an impulse shared between particle and body. The
+    /// impulses are added to `fluid_force` and `fluid_torque`.
+    pub fn collide_particles(
+        &mut self,
+        particles: &mut [Particle],
+        indices: &[usize],
+        delta_time: f32,
+        config: &SimulationConfig,
+    ) {
+        let radius = config.particle_radius as f32;
+        for &index in indices {
+            let particle = &mut particles[index];
+            let (distance, normal) = self.signed_distance(particle.position);
+            if distance >= radius {
+                continue;
+            }
+            let contact = particle.position - normal * distance;
+            particle.position += normal * (radius - distance);
+
+            let approach = (particle.velocity - self.velocity_at(contact)).dot(normal);
+            if approach >= 0.0 {
+                continue;
+            }
+            let impulse =
+                -approach / (1.0 / particle.mass + self.inverse_mass_along(contact, normal));
+            particle.velocity += normal * impulse / particle.mass;
+            self.apply_impulse(normal * -impulse, contact);
+
+            self.fluid_force -= normal * impulse / delta_time;
+            self.fluid_torque -= (contact - self.position).cross(normal) * impulse / delta_time;
+        }
+    }
+
+    /// Moves the body by its velocity and resolves contacts with the solid walls
+    /// and obstacles.
+    pub fn advance(&mut self, delta_time: f32, config: &SimulationConfig) {
+        self.position += self.velocity * delta_time;
+        self.angle += self.angular_velocity * delta_time;
+
+        let (period_x, period_y) = config.walls.period(config.window_size);
+        if let Some(length) = period_x {
+            self.position.x = self.position.x.rem_euclid(length);
+        }
+        if let Some(length) = period_y {
+            self.position.y = self.position.y.rem_euclid(length);
+        }
+
+        // A circle touches the world at its radius around the centre, a polygon
+        // only with its vertices.
+        let (probes, margin): (Vec<Vector>, f32) = match &self.shape {
+            BodyShape::Circle { radius } => (vec![Vector::zero()], *radius),
+            BodyShape::Polygon { points } => {
+                (points.iter().map(|&point| point.into()).collect(), 0.0)
+            }
+        };
+        for probe in probes {
+            self.collide_boundaries(probe, margin, config);
+        }
+    }
+
+    fn collide_boundaries(&mut self, probe: Vector, margin: f32, config: &SimulationConfig) {
+        let point = self.to_world(probe);
+        let size = Vector::from(config.window_size);
+        let sides = &config.walls;
+        let walls = [
+            (sides.left, point.x, Vector::new(1.0, 0.0)),
+            (sides.right, size.x - point.x, Vector::new(-1.0, 0.0)),
+            (sides.top, point.y, Vector::new(0.0, 1.0)),
+            (sides.bottom, size.y - point.y, Vector::new(0.0, -1.0)),
+        ];
+        let walls = walls
+            .into_iter()
+            .filter(|(mode, _, _)| mode.is_solid())
+            .map(|(_, distance, normal)| (distance, normal));
+        let obstacles = config
+            .obstacles
+            .iter()
+            .map(|obstacle| obstacle.signed_distance(point));
+        for (distance, normal) in walls.chain(obstacles) {
+            if distance >= margin {
+                continue;
+            }
+            let contact = point - normal * margin;
+            let approach = self.velocity_at(contact).dot(normal);
+            self.position += normal * (margin - distance);
+            if approach < 0.0 {
+                let impulse = -(1.0 + Self::RESTITUTION) * approach
+                    / self.inverse_mass_along(contact, normal);
+                self.apply_impulse(normal * impulse, contact);
+            }
+        }
+    }
+}
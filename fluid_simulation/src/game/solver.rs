@@ -0,0 +1,260 @@
+use std::fmt::Debug;
+
+use serde::Deserialize;
+
+use crate::config::SimulationConfig;
+
+use super::{
+    boundary::BoundaryLookup, cursor::Cursor, dfsph::Dfsph, executor::Executor, explicit::Explicit,
+    force_model::ForceModel, iisph::Iisph, kernel::Kernels, particle::Particle,
+    particles_lookup::ParticlesLookup, pbf::Pbf, pcisph::Pcisph, utils::particle_rng,
+    vector::Vector,
+};
+
+/// Advances the whole particle set by one step.
+///
+/// A solver owns its scratch buffers and is free to use the neighbour grid in
+/// `SolverStep::lookup` as it likes, as long as every particle ends the step with
+/// its new `position` and `velocity`.
+pub trait Solver: Debug + Send {
+    fn name(&self) -> &'static str;
+
+    fn step(&mut self, step: SolverStep<'_>) -> SolverStats;
+}
+
+/// Everything a [`Solver`] may read or change during one step.
+pub struct SolverStep<'a> {
+    pub lookup: &'a mut ParticlesLookup,
+    /// Samples of the solid surfaces the fluid pushes against, see
+    /// [`BoundaryParticle`](super::boundary::BoundaryParticle). Solvers count
+    /// them in density and pressure and leave the reaction in each particle's
+    /// `boundary_pressure`.
+    pub boundary: &'a BoundaryLookup,
+    pub executor: &'a Executor,
+    pub config: &'a SimulationConfig,
+    pub kernels: &'a Kernels,
+    pub cursor: Cursor,
+    pub delta_time: f32,
+    /// Seed of this step's per-particle random streams, see [`particle_rng`].
+    pub step_seed: u64,
+}
+
+/// Which pressure solver advances the particles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SolverType {
+    /// One explicit pressure pass using the configured force model.
+    Explicit,
+    /// Predictive-corrective incompressible SPH.
+    Pcisph,
+    /// Divergence-free SPH: constant density and zero velocity divergence.
+    Dfsph,
+    /// Position Based Fluids: density constraints projected on positions.
+    Pbf,
+    /// Implicit incompressible SPH: pressure Poisson equation solved with
+    /// relaxed Jacobi.
+    Iisph,
+}
+
+impl SolverType {
+    pub const ALL: [SolverType; 5] = [
+        SolverType::Explicit,
+        SolverType::Pcisph,
+        SolverType::Dfsph,
+        SolverType::Pbf,
+        SolverType::Iisph,
+    ];
+
+    pub fn build(self) -> Box<dyn Solver> {
+        match self {
+            SolverType::Explicit => Box::new(Explicit::new()),
+            SolverType::Pcisph => Box::new(Pcisph::new()),
+            SolverType::Dfsph => Box::new(Dfsph::new()),
+            SolverType::Pbf => Box::new(Pbf::new()),
+            SolverType::Iisph => Box::new(Iisph::new()),
+        }
+    }
+
+    /// The solver after this one in [`SolverType::ALL`], wrapping around.
+    pub fn next(self) -> SolverType {
+        let index = Self::ALL.iter().position(|&solver| solver == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Convergence figures of the last step.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SolverStats {
+    /// Pressure iterations run; 0 for solvers that do not iterate.
+    pub iterations: u32,
+    /// Average compression `max(ρ - ρ₀, 0) / ρ₀` over all particles, with `ρ₀`
+    /// the rest density of each particle's material.
+    pub density_error: f32,
+}
+
+impl SolverStats {
+    /// Average compression of `densities`, the i-th of which belongs to
+    /// `particles[i]`.
+    pub fn average_density_error(
+        densities: impl Iterator<Item = f32>,
+        particles: &[Particle],
+        config: &SimulationConfig,
+    ) -> f32 {
+        let (sum, count) =
+            densities
+                .zip(particles)
+                .fold((0.0, 0), |(sum, count), (density, particle)| {
+                    let rest_density = particle.rest_density(config);
+                    (
+                        sum + (density - rest_density).max(0.0) / rest_density,
+                        count + 1,
+                    )
+                });
+        if count == 0 {
+            0.0
+        } else {
+            sum / count as f32
+        }
+    }
+}
+
+/// Gravity, drag, viscosity, surface tension and cursor acceleration of every
+/// particle, for the solvers that handle pressure on their own.
+pub fn non_pressure_accelerations(
+    out: &mut Vec<Vector>,
+    lookup: &ParticlesLookup,
+    executor: &Executor,
+    config: &SimulationConfig,
+    kernels: &Kernels,
+    cursor: Cursor,
+    step_seed: u64,
+) {
+    executor.map_into(out, lookup.particles.len(), |i| {
+        let mut rng = particle_rng(step_seed, i);
+        let other_particles = lookup.query_neighbors(i).particles();
+        lookup.particles[i].calculate_non_pressure_acceleration(
+            other_particles,
+            cursor,
+            config,
+            kernels,
+            &mut rng,
+        )
+    });
+}
+
+/// `∇W` of the density kernel for the offset `x_i - x_j`.
+pub fn kernel_gradient(kernels: &Kernels, offset: Vector) -> Vector {
+    let dst = offset.magnitude();
+    if dst == 0.0 {
+        return Vector::zero();
+    }
+    offset / dst * kernels.density.gradient(dst)
+}
+
+/// `Σ W` of a particle with a full neighbourhood at `particle_spacing`: the
+/// number density of fluid packed at that spacing.
+pub fn prototype_density(config: &SimulationConfig, kernels: &Kernels) -> f32 {
+    let spacing = config.particle_spacing as f32;
+    let extent = (kernels.density.support_radius() / spacing).ceil() as i32;
+
+    let mut density = 0.0;
+    for x in -extent..=extent {
+        for y in -extent..=extent {
+            let offset = Vector::new(x as f32, y as f32) * spacing;
+            density += kernels.density.value(offset.magnitude());
+        }
+    }
+    density
+}
+
+/// `Σ ∇W` and `Σ |∇W|²` of a particle with a full neighbourhood at
+/// `particle_spacing`, for solvers that need a stiffness independent of the
+/// actual neighbours.
+pub fn prototype_gradient_sums(config: &SimulationConfig, kernels: &Kernels) -> (Vector, f32) {
+    let spacing = config.particle_spacing as f32;
+    let extent = (kernels.density.support_radius() / spacing).ceil() as i32;
+
+    let mut gradient_sum = Vector::zero();
+    let mut gradient_dot_sum = 0.0;
+    for x in -extent..=extent {
+        for y in -extent..=extent {
+            let gradient = kernel_gradient(kernels, Vector::new(x as f32, y as f32) * spacing);
+            gradient_sum += gradient;
+            gradient_dot_sum += gradient.dot(gradient);
+        }
+    }
+    (gradient_sum, gradient_dot_sum)
+}
+
+/// First stage shared by the solvers: moves `predicted_position` one step ahead,
+/// rebuilds the neighbour grid and stores density, near density (for
+/// `ForceModel::DoubleDensity`) and surface normal (with surface tension) at the
+/// predicted positions. [`DensityPass::evaluate`] skips the prediction for
+/// solvers that work on other positions.
+#[derive(Debug, Default)]
+pub struct DensityPass {
+    densities: Vec<(f32, f32)>,
+    surface_normals: Vec<Vector>,
+}
+
+impl DensityPass {
+    pub fn run(&mut self, step: &mut SolverStep<'_>) {
+        let delta_time = step.delta_time;
+        step.executor
+            .for_each_mut(&mut step.lookup.particles, |_, particle| {
+                particle.predict(delta_time)
+            });
+        self.evaluate(step);
+    }
+
+    /// Rebuilds the neighbour grid and stores the same quantities as
+    /// [`Self::run`] at the `predicted_position` the particles already have.
+    pub fn evaluate(&mut self, step: &mut SolverStep<'_>) {
+        let (executor, config, kernels) = (step.executor, step.config, step.kernels);
+        let boundary = step.boundary;
+        step.lookup.update_cells();
+
+        let lookup = &*step.lookup;
+        executor.map_into(&mut self.densities, lookup.particles.len(), |i| {
+            let current = &lookup.particles[i];
+            let point = current.predicted_position;
+            let other_particles = lookup.query_neighbors(i).particles();
+            let boundary_samples = boundary.query_around(point).particles();
+            let density = current.calculate_density(
+                point,
+                other_particles.clone(),
+                boundary_samples,
+                kernels,
+            );
+            let near_density = match config.force_model {
+                ForceModel::Pressure => 0.0,
+                ForceModel::DoubleDensity => {
+                    current.calculate_near_density(point, other_particles, kernels)
+                }
+            };
+            (density, near_density)
+        });
+        let densities = &self.densities;
+        executor.for_each_mut(&mut step.lookup.particles, |i, particle| {
+            (particle.density, particle.near_density) = densities[i]
+        });
+        step.lookup.sync_ghosts();
+
+        if config.surface_tension != 0.0 {
+            let lookup = &*step.lookup;
+            executor.map_into(&mut self.surface_normals, lookup.particles.len(), |i| {
+                let other_particles = lookup.query_neighbors(i).particles();
+                lookup.particles[i].calculate_surface_normal(other_particles, config, kernels)
+            });
+            let surface_normals = &self.surface_normals;
+            executor.for_each_mut(&mut step.lookup.particles, |i, particle| {
+                particle.surface_normal = surface_normals[i]
+            });
+            step.lookup.sync_ghosts();
+        }
+    }
+
+    pub fn densities(&self) -> impl Iterator<Item = f32> + '_ {
+        self.densities.iter().map(|&(density, _)| density)
+    }
+}
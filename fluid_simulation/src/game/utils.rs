@@ -1,62 +1,52 @@
 use std::f32::consts::PI;
 
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-use crate::constants;
+use crate::config::SimulationConfig;
 
-use super::{particle::Particle, particles_lookup::ParticlesLookup, vector::Vector};
+use super::{kernel::Kernel, particle::Particle, vector::Vector};
 
-pub fn calculate_density(point: Vector, other_particles: &Vec<Particle>) -> f32 {
+/// Density at `point` from the predicted positions, which the neighbour
+/// lookup is filed by.
+pub fn calculate_density<'a>(
+    point: Vector,
+    other_particles: impl Iterator<Item = &'a Particle>,
+    kernel: &dyn Kernel,
+) -> f32 {
     let mut density = 0.0;
     for p in other_particles {
-        let dst = (p.position - point).magnitude();
-        let influence = smoothing_kernel(dst, p.get_smoothing_radius() as f32);
-        density += Particle::MASS * influence;
+        let dst = (p.predicted_position - point).magnitude();
+        let influence = kernel.value(dst);
+        density += p.mass * influence;
     }
-    return density;
+    density
 }
 
-// Integrate[(s-x)^3x,{x,0,s},{θ,0,2π}]
-pub fn smoothing_kernel(dst: f32, radius: f32) -> f32 {
-    if dst >= radius {
-        return 0.0;
-    }
-
-    let volume = (PI * radius.powi(4)) / 6.0;
-    return (radius - dst).powi(2) / volume;
-}
-
-pub fn smoothing_kernel_derivative(dst: f32, radius: f32) -> f32 {
-    if dst >= radius {
-        return 0.0;
-    }
-
-    let scale = 12.0 / (radius.powi(4) * PI);
-    return (dst - radius) * scale;
+pub fn density_to_pressure(particle: &Particle, config: &SimulationConfig) -> f32 {
+    let material = config.material(particle.material);
+    config
+        .equation_of_state
+        .pressure(particle.density, material, config)
 }
 
-pub fn viscosity_smoothing_kernel(dst: f32, radius: f32) -> f32 {
-    if dst >= radius {
-        return 0.0;
-    }
-
-    let volume = (PI * radius.powi(8)) / 4.0;
-    let value = (radius.powi(2) - dst.powi(2)).max(0.0);
-    return value.powi(3) / volume;
+pub fn near_density_to_pressure(near_density: f32, config: &SimulationConfig) -> f32 {
+    config.near_pressure_constant * near_density
 }
 
-pub fn density_to_pressure(density: f32) -> f32 {
-    return constants::PRESSURE_CONSTANT * (density - constants::DENSITY_FLOOR);
+pub fn calculate_shared_pressure(a: &Particle, b: &Particle, config: &SimulationConfig) -> f32 {
+    let pressure_a = density_to_pressure(a, config);
+    let pressure_b = density_to_pressure(b, config);
+    (pressure_a + pressure_b) / 2.0
 }
 
-pub fn calculate_shared_pressure(density_a: f32, density_b: f32) -> f32 {
-    let pressure_a = density_to_pressure(density_a);
-    let pressure_b = density_to_pressure(density_b);
-    (pressure_a + pressure_b) / 2.0
+/// Per-particle random stream derived from one value of the simulation RNG, so
+/// results do not depend on how the work is split across threads.
+pub fn particle_rng(step_seed: u64, index: usize) -> StdRng {
+    StdRng::seed_from_u64(step_seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
 }
 
-pub fn random_direction() -> Vector {
-    let mut rng = rand::thread_rng();
+pub fn random_direction(rng: &mut impl Rng) -> Vector {
     let angle = rng.gen_range(0.0..2.0 * PI);
     let x = angle.cos();
     let y = angle.sin();
@@ -75,6 +75,17 @@ impl Vector {
         (*self * other).sum()
     }
 
+    /// z component of the 3D cross product, `self.x * other.y - self.y * other.x`.
+    pub fn cross(&self, other: Vector) -> f32 {
+        self.x * other.y - self.y * other.x
+    }
+
+    /// Rotates counter-clockwise in a y-up frame, i.e. clockwise on screen.
+    pub fn rotate(&self, angle: f32) -> Vector {
+        let (sin, cos) = angle.sin_cos();
+        Vector::new(self.x * cos - self.y * sin, self.x * sin + self.y * cos)
+    }
+
     pub fn magnitude(&self) -> f32 {
         self.dot(*self).sqrt()
     }
@@ -82,9 +93,9 @@ impl Vector {
     pub fn normalize(&self) -> Vector {
         let magnitude = self.magnitude();
         if magnitude == 0.0 {
-            return Vector::zero();
+            Vector::zero()
         } else {
-            return Vector::new(self.x / magnitude, self.y / magnitude);
+            Vector::new(self.x / magnitude, self.y / magnitude)
         }
     }
 
@@ -123,7 +134,7 @@ impl From<(i32, i32)> for Vector {
 
 impl From<(f32, f32)> for Vector {
     fn from(size: (f32, f32)) -> Self {
-        Vector::new(size.0 as f32, size.1 as f32)
+        Vector::new(size.0, size.1)
     }
 }
 
@@ -0,0 +1,71 @@
+use serde::Deserialize;
+
+use super::vector::Vector;
+
+/// How one side of the window treats the particles that reach it.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum WallMode {
+    /// Lined with boundary samples. A particle touching the wall keeps
+    /// `restitution` of its speed towards it, bounced back, and `friction`
+    /// takes that share of the velocity along the wall off every particle
+    /// within the smoothing radius of it every 1/60 s: 0 is free-slip, 1 is
+    /// no-slip.
+    Solid {
+        #[serde(default)]
+        restitution: f32,
+        #[serde(default)]
+        friction: f32,
+    },
+    /// Particles leaving here re-enter through the opposite side, which must be
+    /// periodic as well. Neighbours are found across the seam.
+    Periodic,
+    /// Particles leaving here are removed from the simulation.
+    Open,
+}
+
+impl Default for WallMode {
+    fn default() -> Self {
+        WallMode::Solid {
+            restitution: 0.0,
+            friction: 0.0,
+        }
+    }
+}
+
+impl WallMode {
+    pub fn is_solid(&self) -> bool {
+        matches!(self, WallMode::Solid { .. })
+    }
+}
+
+/// The [`WallMode`] of each side of the window. `top` is at `y = 0`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Walls {
+    pub left: WallMode,
+    pub right: WallMode,
+    pub top: WallMode,
+    pub bottom: WallMode,
+}
+
+impl Walls {
+    /// Width and height of the window along the axes that wrap around.
+    pub fn period(&self, window_size: (u32, u32)) -> (Option<f32>, Option<f32>) {
+        let periodic =
+            |mode: WallMode, length: u32| (mode == WallMode::Periodic).then_some(length as f32);
+        (
+            periodic(self.left, window_size.0),
+            periodic(self.top, window_size.1),
+        )
+    }
+
+    /// Whether `position` lies past one of the open sides.
+    pub fn is_outflow(&self, position: Vector, window_size: (u32, u32)) -> bool {
+        let size = Vector::from(window_size);
+        (self.left == WallMode::Open && position.x < 0.0)
+            || (self.right == WallMode::Open && position.x > size.x)
+            || (self.top == WallMode::Open && position.y < 0.0)
+            || (self.bottom == WallMode::Open && position.y > size.y)
+    }
+}
@@ -0,0 +1,18 @@
+pub mod config;
+pub mod constants;
+pub mod game;
+pub mod simulation;
+pub mod timestep;
+
+pub use config::{ConfigError, SimulationConfig};
+pub use game::cursor::{Cursor, CursorForceType};
+pub use game::emitter::{Emitter, EmitterConfig, EmitterShape};
+pub use game::integrator::Integrator;
+pub use game::material::Material;
+pub use game::obstacle::Obstacle;
+pub use game::particle::Particle;
+pub use game::rigid_body::{BodyShape, RigidBody, RigidBodyConfig};
+pub use game::solver::{Solver, SolverStats, SolverStep, SolverType};
+pub use game::vector::Vector;
+pub use simulation::Simulation;
+pub use timestep::FixedTimestep;
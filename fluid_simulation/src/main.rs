@@ -2,113 +2,106 @@
 
 extern crate sdl2;
 
-use game::particle::Particle;
-use game::vector::Vector;
+use sdl2::event::Event;
 use sdl2::keyboard::{Keycode, Mod};
 use sdl2::mouse;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::{BlendMode, WindowCanvas};
-use sdl2::video::Window;
-use sdl2::{event::Event, rect::Point};
 use std::time::{Duration, Instant};
 
-mod constants;
-mod game;
-use game::game_context::GameContext;
+use fluid_simulation::game::game_context::GameState;
+use fluid_simulation::{Cursor, CursorForceType, Particle, Simulation, SimulationConfig, Vector};
 
-use crate::game::cursor::{self, Cursor, CursorForceType};
-use crate::game::game_context::GameState;
+mod renderer;
+use renderer::Renderer;
 
-pub struct Renderer {
-    canvas: WindowCanvas,
-}
-
-impl Renderer {
-    pub fn new(window: Window) -> Result<Renderer, String> {
-        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
-        Ok(Renderer { canvas })
-    }
-
-    pub fn draw(&mut self, context: &GameContext, show_heatmap: bool) -> Result<(), String> {
-        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-        self.canvas.clear();
-        self.canvas.set_blend_mode(BlendMode::Blend);
-
-        if show_heatmap {
-            self.draw_heatmap(context)?;
-        }
+/// How often the HUD in the window title and the `--stats` output refresh.
+const STATS_INTERVAL: Duration = Duration::from_millis(250);
 
-        for particle in context.particles_lookup.particles.iter() {
-            self.draw_circle(
-                particle.position.try_into().unwrap(),
-                Particle::RADIUS,
-                Particle::COLOR,
-            )
-        }
-
-        self.canvas.present();
-        Ok(())
-    }
+struct Options {
+    config: SimulationConfig,
+    print_stats: bool,
+}
 
-    pub fn draw_circle(&mut self, center: (i32, i32), radius: u32, color: Color) {
-        let (x, y) = center;
-        let radius = radius as f64;
-        self.canvas.set_draw_color(color);
-        for dy in (-radius as i32)..=(radius as i32) {
-            let dx = (radius.powi(2) - (dy as f64).powi(2)).sqrt() as i32; // dx^2 + dy^2 = radius^2
-            let x1 = x.checked_sub(dx);
-            let x2 = x.checked_add(dx);
-            if let (Some(x1), Some(x2)) = (x1, x2) {
-                if let Some(y_dy) = y.checked_add(dy) {
-                    self.canvas.draw_line((x1, y_dy), (x2, y_dy)).unwrap();
-                }
+fn load_options() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut config = SimulationConfig::default();
+    let mut seed = None;
+    let mut print_stats = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "-c" => {
+                let path = args.next().ok_or("--config expects a path")?;
+                config = SimulationConfig::load(&path).map_err(|e| format!("{}: {}", path, e))?;
+            }
+            "--seed" | "-s" => {
+                let value = args.next().ok_or("--seed expects a number")?;
+                let value = value
+                    .parse::<u64>()
+                    .map_err(|e| format!("invalid seed `{}`: {}", value, e))?;
+                seed = Some(value);
             }
+            "--stats" => print_stats = true,
+            _ => return Err(format!("unknown argument `{}`", arg)),
         }
     }
-
-    pub fn draw_rect(&mut self, pos: (i32, i32), size: (u32, u32), color: Color) {
-        let rect = Rect::new(pos.0, pos.1, size.0, size.1);
-        self.canvas.set_draw_color(color);
-        self.canvas.fill_rect(rect).unwrap();
+    if seed.is_some() {
+        config.seed = seed;
     }
+    Ok(Options {
+        config,
+        print_stats,
+    })
+}
 
-    pub fn draw_heatmap(&mut self, context: &GameContext) -> Result<(), String> {
-        let res = context.heatmap_resolution as f32; // Convert to i32
-        for x in 0..context.heatmap.len() {
-            for y in 0..context.heatmap[0].len() {
-                let alpha = context.heatmap[x][y] * 255.0 * 100.0;
-                let pos = Vector::new(x as f32, y as f32) * res - res / 2.0;
-                let size = (res as u32, res as u32);
-
-                self.draw_rect(
-                    pos.try_into().unwrap(),
-                    size,
-                    Color::RGBA(255, 0, 0, alpha as u8),
-                );
-            }
-        }
-        Ok(())
-    }
+fn stats_line(simulation: &Simulation) -> String {
+    let stats = simulation.solver_stats();
+    format!(
+        "{} | {} particles | dt {:.2} ms | {} it | density error {:.2}% | energy {:.4e}",
+        simulation.solver_name(),
+        simulation.particle_count(),
+        simulation.delta_time() * 1000.0,
+        stats.iterations,
+        stats.density_error * 100.0,
+        simulation.energy()
+    )
+}
+
+/// Drops a square of particles of the first material centred on `center`.
+fn add_block(simulation: &mut Simulation, center: Vector) {
+    let config = simulation.config();
+    let spacing = config.particle_spacing as f32;
+    let particles: Vec<Particle> = (-2..=2)
+        .flat_map(|x| (-2..=2).map(move |y| Vector::new(x as f32, y as f32)))
+        .map(|offset| Particle::spawn(center + offset * spacing, Vector::zero(), 0, config))
+        .collect();
+    simulation.context_mut().add_particles(particles);
 }
 
 pub fn main() -> Result<(), String> {
+    let Options {
+        config,
+        print_stats,
+    } = load_options()?;
+
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
     let window = video_subsystem
         .window(
             "rust-sdl2 demo: Video",
-            constants::WINDOW_SIZE.0,
-            constants::WINDOW_SIZE.1,
+            config.window_size.0,
+            config.window_size.1,
         )
         .position_centered()
         .opengl()
         .build()
         .map_err(|e| e.to_string())?;
 
-    let mut context = GameContext::new(true, 8);
-    context.update_heatmap();
+    let fps = config.fps;
+    let cursor_radius = config.cursor_radius;
+    let mut simulation = Simulation::new(config, true);
+    println!("seed: {}", simulation.seed());
+    println!("solver: {}", simulation.solver_name());
+    simulation.context_mut().update_heatmap();
 
     let mut renderer = Renderer::new(window)?;
 
@@ -119,18 +112,17 @@ pub fn main() -> Result<(), String> {
     let mut cursor = Cursor::new(
         Vector::new(-1.0, -1.0),
         CursorForceType::None,
-        constants::CURSOR_RADIUS,
+        cursor_radius,
     );
 
     let mut step_frame = false;
-    let mut frame_count = 0;
-    let mut fps_time = Instant::now();
-    let mut fps = constants::FPS as f32;
-    let mut delta_time = 0.0;
-    const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / constants::FPS);
+    let mut last_stats = Instant::now();
+    let mut last_frame = Instant::now();
+    let frame_duration = Duration::from_nanos(1_000_000_000 / fps);
     'running: loop {
         let frame_start = Instant::now();
-        frame_count += 1;
+        let frame_time = (frame_start - last_frame).as_secs_f32();
+        last_frame = frame_start;
 
         for event in event_pump.poll_iter() {
             match event {
@@ -141,21 +133,28 @@ pub fn main() -> Result<(), String> {
                     ..
                 } => match (keycode, keymod) {
                     (Keycode::Escape, _) => break 'running,
-                    (Keycode::Space, _) => context.toggle_pause(),
-                    (Keycode::R, keymod) if keymod.contains(Mod::LSHIFTMOD) => context.reset(false),
-                    (Keycode::R, _) => context.reset(true),
+                    (Keycode::Space, _) => simulation.context_mut().toggle_pause(),
+                    (Keycode::R, keymod) if keymod.contains(Mod::LSHIFTMOD) => {
+                        simulation.reset(false)
+                    }
+                    (Keycode::R, _) => simulation.reset(true),
                     (Keycode::H, _) => show_heatmap = true,
+                    (Keycode::S, _) => {
+                        let solver = simulation.config().solver.next();
+                        simulation.set_solver(solver);
+                        println!("solver: {}", simulation.solver_name());
+                    }
                     (Keycode::Right, _) => step_frame = true,
+                    (Keycode::A, _) => add_block(&mut simulation, cursor.position),
+                    (Keycode::D, _) => {
+                        simulation.remove_particles_within(cursor.position, cursor.radius);
+                    }
                     _ => {}
                 },
                 Event::KeyUp {
-                    keycode: Some(keycode),
-                    keymod,
+                    keycode: Some(Keycode::H),
                     ..
-                } => match (keycode, keymod) {
-                    (Keycode::H, _) => show_heatmap = false,
-                    _ => {}
-                },
+                } => show_heatmap = false,
                 Event::MouseMotion { x, y, .. } => {
                     cursor.position.x = x as f32;
                     cursor.position.y = y as f32;
@@ -168,41 +167,39 @@ pub fn main() -> Result<(), String> {
                 Event::MouseButtonUp { .. } => cursor.force_type = CursorForceType::None,
                 Event::MouseWheel { y, .. } => {
                     cursor.radius += y as f32 * 10.0;
-                    cursor.radius.max(0.0);
+                    cursor.radius = cursor.radius.max(0.0);
                 }
                 _ => {}
             }
         }
 
-        if context.state == GameState::Playing || step_frame {
-            context.update(cursor, delta_time);
-            step_frame = false;
+        if simulation.context().state == GameState::Playing {
+            simulation.advance(cursor, frame_time);
+        } else if step_frame {
+            simulation.step_fixed(cursor);
         }
+        step_frame = false;
 
         if show_heatmap {
-            context.update_heatmap();
+            simulation.context_mut().update_heatmap();
         }
 
-        if let Err(e) = renderer.draw(&context, show_heatmap) {
+        if let Err(e) = renderer.draw(simulation.context(), show_heatmap) {
             eprintln!("An error occurred while drawing: {}", e);
         }
 
-        let elapsed = fps_time.elapsed();
-        if elapsed >= Duration::from_secs(1) {
-            fps = frame_count as f32 / elapsed.as_secs_f32();
-            delta_time = if fps != 0.0 {
-                1.0 / fps.max(constants::FPS as f32)
-            } else {
-                1.0 / constants::FPS as f32
-            };
-
-            frame_count = 0;
-            fps_time = Instant::now();
+        if frame_start - last_stats >= STATS_INTERVAL {
+            last_stats = frame_start;
+            let line = stats_line(&simulation);
+            if print_stats {
+                println!("{}", line);
+            }
+            renderer.set_title(&line)?;
         }
 
-        let frame_time = Instant::now() - frame_start;
-        if frame_time < FRAME_DURATION {
-            ::std::thread::sleep(FRAME_DURATION - frame_time);
+        let render_time = Instant::now() - frame_start;
+        if render_time < frame_duration {
+            ::std::thread::sleep(frame_duration - render_time);
         }
     }
 
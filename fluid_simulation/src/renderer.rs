@@ -0,0 +1,180 @@
+use fluid_simulation::game::game_context::GameContext;
+use fluid_simulation::Obstacle;
+use fluid_simulation::Vector;
+use fluid_simulation::{BodyShape, RigidBody};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, WindowCanvas};
+use sdl2::video::Window;
+
+pub struct Renderer {
+    canvas: WindowCanvas,
+}
+
+impl Renderer {
+    pub const OBSTACLE_COLOR: Color = Color::RGB(128, 128, 128);
+    pub const BODY_COLOR: Color = Color::RGB(170, 110, 60);
+    pub const DRAIN_COLOR: Color = Color::RGB(70, 30, 30);
+    pub const EMITTER_COLOR: Color = Color::RGB(60, 160, 60);
+
+    pub fn new(window: Window) -> Result<Renderer, String> {
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        Ok(Renderer { canvas })
+    }
+
+    pub fn draw(&mut self, context: &GameContext, show_heatmap: bool) -> Result<(), String> {
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        self.canvas.set_blend_mode(BlendMode::Blend);
+
+        if show_heatmap {
+            self.draw_heatmap(context)?;
+        }
+
+        for drain in &context.config.drains {
+            self.draw_obstacle(drain, Self::DRAIN_COLOR);
+        }
+
+        for obstacle in &context.config.obstacles {
+            self.draw_obstacle(obstacle, Self::OBSTACLE_COLOR);
+        }
+
+        for slot in context.emitters.iter().flat_map(|emitter| &emitter.slots) {
+            self.draw_circle(
+                (slot.x as i32, slot.y as i32),
+                context.config.particle_radius,
+                Self::EMITTER_COLOR,
+            );
+        }
+
+        for body in &context.rigid_bodies {
+            self.draw_rigid_body(body, Self::BODY_COLOR);
+        }
+
+        for particle in context.particles_lookup.particles.iter() {
+            let (r, g, b) = context.config.material(particle.material).color;
+            self.draw_circle(
+                particle.position.try_into().unwrap(),
+                context.config.particle_radius,
+                Color::RGB(r, g, b),
+            )
+        }
+
+        self.canvas.present();
+        Ok(())
+    }
+
+    /// Shows `text` in the window title, which doubles as the HUD.
+    pub fn set_title(&mut self, text: &str) -> Result<(), String> {
+        self.canvas
+            .window_mut()
+            .set_title(text)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn draw_circle(&mut self, center: (i32, i32), radius: u32, color: Color) {
+        let (x, y) = center;
+        let radius = radius as f64;
+        self.canvas.set_draw_color(color);
+        for dy in (-radius as i32)..=(radius as i32) {
+            let dx = (radius.powi(2) - (dy as f64).powi(2)).sqrt() as i32; // dx^2 + dy^2 = radius^2
+            let x1 = x.checked_sub(dx);
+            let x2 = x.checked_add(dx);
+            if let (Some(x1), Some(x2)) = (x1, x2) {
+                if let Some(y_dy) = y.checked_add(dy) {
+                    self.canvas.draw_line((x1, y_dy), (x2, y_dy)).unwrap();
+                }
+            }
+        }
+    }
+
+    pub fn draw_rect(&mut self, pos: (i32, i32), size: (u32, u32), color: Color) {
+        let rect = Rect::new(pos.0, pos.1, size.0, size.1);
+        self.canvas.set_draw_color(color);
+        self.canvas.fill_rect(rect).unwrap();
+    }
+
+    pub fn draw_obstacle(&mut self, obstacle: &Obstacle, color: Color) {
+        match obstacle {
+            Obstacle::Circle { center, radius } => {
+                self.draw_circle((center.0 as i32, center.1 as i32), *radius as u32, color)
+            }
+            Obstacle::Box { center, size } => self.draw_rect(
+                (
+                    (center.0 - size.0 / 2.0) as i32,
+                    (center.1 - size.1 / 2.0) as i32,
+                ),
+                (size.0 as u32, size.1 as u32),
+                color,
+            ),
+            Obstacle::Polygon { points } => self.draw_polygon(points, color),
+        }
+    }
+
+    /// Draws the body in its current pose with a line from the centre of mass to
+    /// the outline, so rotation stays visible on circles.
+    pub fn draw_rigid_body(&mut self, body: &RigidBody, color: Color) {
+        let marker = match &body.shape {
+            BodyShape::Circle { radius } => {
+                let center = body.position;
+                self.draw_circle((center.x as i32, center.y as i32), *radius as u32, color);
+                Vector::new(*radius, 0.0)
+            }
+            BodyShape::Polygon { points } => {
+                let world_points = points
+                    .iter()
+                    .map(|&point| body.to_world(point.into()).try_into().unwrap())
+                    .collect::<Vec<(f32, f32)>>();
+                self.draw_polygon(&world_points, color);
+                Vector::from(points[0])
+            }
+        };
+        let start: (i32, i32) = body.position.try_into().unwrap();
+        let end: (i32, i32) = body.to_world(marker).try_into().unwrap();
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.draw_line(start, end).unwrap();
+    }
+
+    /// Fills a polygon row by row with the even-odd rule.
+    pub fn draw_polygon(&mut self, points: &[(f32, f32)], color: Color) {
+        let min_y = points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+        let max_y = points.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+        self.canvas.set_draw_color(color);
+
+        let mut crossings = Vec::new();
+        for y in (min_y.ceil() as i32)..=(max_y.floor() as i32) {
+            let row = y as f32;
+            crossings.clear();
+            for (i, &(x1, y1)) in points.iter().enumerate() {
+                let (x0, y0) = points[(i + points.len() - 1) % points.len()];
+                if (y0 > row) != (y1 > row) {
+                    crossings.push(x0 + (row - y0) / (y1 - y0) * (x1 - x0));
+                }
+            }
+            crossings.sort_by(|a, b| a.total_cmp(b));
+            for span in crossings.chunks_exact(2) {
+                self.canvas
+                    .draw_line((span[0] as i32, y), (span[1] as i32, y))
+                    .unwrap();
+            }
+        }
+    }
+
+    pub fn draw_heatmap(&mut self, context: &GameContext) -> Result<(), String> {
+        let res = context.heatmap_resolution as f32; // Convert to i32
+        for x in 0..context.heatmap.len() {
+            for y in 0..context.heatmap[0].len() {
+                let alpha = context.heatmap[x][y] * 255.0 * 100.0;
+                let pos = Vector::new(x as f32, y as f32) * res - res / 2.0;
+                let size = (res as u32, res as u32);
+
+                self.draw_rect(
+                    pos.try_into().unwrap(),
+                    size,
+                    Color::RGBA(255, 0, 0, alpha as u8),
+                );
+            }
+        }
+        Ok(())
+    }
+}
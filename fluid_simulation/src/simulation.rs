@@ -0,0 +1,946 @@
+use crate::config::SimulationConfig;
+use crate::game::cursor::{Cursor, CursorForceType};
+use crate::game::emitter::Emitter;
+use crate::game::game_context::GameContext;
+use crate::game::particle::Particle;
+use crate::game::rigid_body::RigidBody;
+use crate::game::solver::{SolverStats, SolverType};
+use crate::game::utils::calculate_density;
+use crate::game::vector::Vector;
+use crate::timestep::FixedTimestep;
+
+/// Headless entry point into the fluid simulation.
+///
+/// Owns a [`GameContext`] and exposes the operations a frontend or tool needs
+/// to build, step and inspect the particle system without pulling in SDL.
+pub struct Simulation {
+    context: GameContext,
+    timestep: FixedTimestep,
+}
+
+impl Simulation {
+    pub const HEATMAP_RESOLUTION: u32 = 8;
+
+    pub fn new(config: SimulationConfig, use_random_pos: bool) -> Simulation {
+        Self::from_context(GameContext::new(
+            config,
+            use_random_pos,
+            Self::HEATMAP_RESOLUTION,
+        ))
+    }
+
+    pub fn from_context(context: GameContext) -> Simulation {
+        let timestep = FixedTimestep::new(
+            context.config.timestep,
+            context.config.substeps,
+            context.config.max_steps_per_frame,
+        );
+        Simulation { context, timestep }
+    }
+
+    /// Feeds `frame_time` seconds of wall-clock time into the fixed-timestep
+    /// accumulator and runs every physics step that became due. Returns the
+    /// number of fixed steps taken.
+    pub fn advance(&mut self, cursor: Cursor, frame_time: f32) -> u32 {
+        let steps = self.timestep.advance(frame_time);
+        for _ in 0..steps {
+            self.step_fixed(cursor);
+        }
+        steps
+    }
+
+    /// Runs exactly one fixed step, split into the configured substeps or, with
+    /// `adaptive_timestep`, into as many stable substeps as it takes.
+    pub fn step_fixed(&mut self, cursor: Cursor) {
+        if self.context.config.adaptive_timestep {
+            let mut remaining = self.timestep.step;
+            while remaining > 0.0 {
+                let mut delta_time = self.context.stable_timestep().min(remaining);
+                // Fold a sliver that would leave a degenerate last substep into this one.
+                if remaining - delta_time < self.context.config.min_timestep {
+                    delta_time = remaining;
+                }
+                self.context.update(cursor, delta_time);
+                remaining -= delta_time;
+            }
+            return;
+        }
+
+        let delta_time = self.timestep.substep();
+        for _ in 0..self.timestep.substeps {
+            self.context.update(cursor, delta_time);
+        }
+    }
+
+    pub fn timestep(&self) -> &FixedTimestep {
+        &self.timestep
+    }
+
+    /// Advances the simulation by `delta_time` seconds without any cursor interaction.
+    pub fn step(&mut self, delta_time: f32) {
+        let cursor = Cursor::new(Vector::new(-1.0, -1.0), CursorForceType::None, 0.0);
+        self.step_with_cursor(cursor, delta_time);
+    }
+
+    pub fn step_with_cursor(&mut self, cursor: Cursor, delta_time: f32) {
+        self.context.update(cursor, delta_time);
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.context.particles_lookup.particles
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.context.particles_lookup.particles.len()
+    }
+
+    /// Adds a particle of `material` and returns whether it was added. It is not
+    /// when `max_particles` is reached or `material` is not an index into
+    /// `config().materials`.
+    pub fn add_particle(&mut self, position: Vector, velocity: Vector, material: usize) -> bool {
+        if material >= self.context.config.materials.len() {
+            return false;
+        }
+        let particle = Particle::spawn(position, velocity, material, &self.context.config);
+        self.context.add_particles([particle]) == 1
+    }
+
+    /// Removes particle `index` and returns it, or `None` if there is no such
+    /// particle. Later particles move down by one.
+    pub fn remove_particle(&mut self, index: usize) -> Option<Particle> {
+        if index >= self.particle_count() {
+            return None;
+        }
+        Some(self.context.particles_lookup.remove(index))
+    }
+
+    /// Removes the particles within `radius` of `center` and returns how many
+    /// were removed.
+    pub fn remove_particles_within(&mut self, center: Vector, radius: f32) -> usize {
+        self.context
+            .remove_particles(|particle| (particle.position - center).magnitude() <= radius)
+    }
+
+    pub fn rigid_bodies(&self) -> &[RigidBody] {
+        &self.context.rigid_bodies
+    }
+
+    pub fn emitters(&self) -> &[Emitter] {
+        &self.context.emitters
+    }
+
+    pub fn density_at(&self, point: Vector) -> f32 {
+        let radius = self.context.config.smoothing_radius as f32;
+        let other_particles = self
+            .context
+            .particles_lookup
+            .query_radius(point, radius)
+            .particles();
+        calculate_density(
+            point,
+            other_particles,
+            self.context.kernels.density.as_ref(),
+        )
+    }
+
+    /// Kinetic plus gravitational potential energy, with the potential measured
+    /// from the bottom of the window. Useful for comparing integrators.
+    pub fn energy(&self) -> f32 {
+        let config = &self.context.config;
+        let floor = config.window_size.1 as f32;
+        self.particles()
+            .iter()
+            .map(|particle| {
+                particle.mass
+                    * (0.5 * particle.velocity.dot(particle.velocity)
+                        + config.gravity * (floor - particle.position.y))
+            })
+            .sum()
+    }
+
+    /// Length of the most recent substep in seconds.
+    pub fn delta_time(&self) -> f32 {
+        self.context.delta_time
+    }
+
+    /// Name of the solver advancing the particles.
+    pub fn solver_name(&self) -> &'static str {
+        self.context.solver().name()
+    }
+
+    pub fn set_solver(&mut self, solver: SolverType) {
+        self.context.set_solver_type(solver);
+    }
+
+    /// Iteration count and density error of the most recent step.
+    pub fn solver_stats(&self) -> SolverStats {
+        self.context.solver_stats
+    }
+
+    /// Seed of the current run. Building a simulation with the same config and
+    /// this seed and feeding it the same steps yields bit-identical particles.
+    pub fn seed(&self) -> u64 {
+        self.context.seed
+    }
+
+    pub fn config(&self) -> &SimulationConfig {
+        &self.context.config
+    }
+
+    pub fn reset(&mut self, use_random_pos: bool) {
+        self.timestep.reset();
+        self.context.reset(use_random_pos);
+    }
+
+    pub fn context(&self) -> &GameContext {
+        &self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut GameContext {
+        &mut self.context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::emitter::{EmitterConfig, EmitterShape};
+    use crate::game::equation_of_state::EquationOfState;
+    use crate::game::force_model::ForceModel;
+    use crate::game::integrator::Integrator;
+    use crate::game::material::Material;
+    use crate::game::obstacle::Obstacle;
+    use crate::game::rigid_body::{BodyShape, RigidBodyConfig};
+    use crate::game::walls::{WallMode, Walls};
+
+    fn seeded(seed: u64) -> Simulation {
+        let config = SimulationConfig {
+            particle_amt: 300,
+            seed: Some(seed),
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, true);
+        // Stacked particles take the random-direction fallback for zero distances.
+        for _ in 0..3 {
+            simulation.add_particle(Vector::new(600.0, 450.0), Vector::zero(), 0);
+        }
+        simulation
+    }
+
+    fn run(simulation: &mut Simulation) {
+        for _ in 0..60 {
+            simulation.step(1.0 / 60.0);
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_identical_runs() {
+        let mut a = seeded(42);
+        let mut b = seeded(42);
+        assert_eq!(a.particles(), b.particles());
+        run(&mut a);
+        run(&mut b);
+        assert_eq!(a.particles(), b.particles());
+
+        let stacked = &a.particles()[300..];
+        assert_ne!(stacked[0].position, stacked[1].position);
+        assert_ne!(stacked[1].position, stacked[2].position);
+    }
+
+    #[test]
+    fn different_seeds_give_different_runs() {
+        let mut a = seeded(42);
+        let mut b = seeded(43);
+        run(&mut a);
+        run(&mut b);
+        assert_ne!(a.particles(), b.particles());
+    }
+
+    /// Drops a small block of fluid into a narrow tank, lets it settle for
+    /// `seconds` and returns the highest remaining particle speed.
+    fn settled_max_speed(solver: SolverType, seconds: f32) -> f32 {
+        let config = SimulationConfig {
+            window_size: (120, 200),
+            particle_amt: 256,
+            solver,
+            seed: Some(1),
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        for _ in 0..(seconds * 60.0) as usize {
+            simulation.step(1.0 / 60.0);
+        }
+        simulation
+            .particles()
+            .iter()
+            .map(|particle| particle.velocity.magnitude())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn iterative_solvers_rest_at_the_packed_density() {
+        let mut simulation = Simulation::new(SimulationConfig::default(), false);
+        let density_floor = simulation.config().density_floor;
+        assert_eq!(simulation.config().material(0).rest_density, density_floor);
+
+        for solver in [
+            SolverType::Pcisph,
+            SolverType::Dfsph,
+            SolverType::Iisph,
+            SolverType::Pbf,
+        ] {
+            simulation.set_solver(solver);
+            let rest_density = simulation.config().material(0).rest_density;
+            assert!(
+                (rest_density - 1.0 / 36.0).abs() < 0.002,
+                "{:?} rest density {} at spacing 6",
+                solver,
+                rest_density
+            );
+        }
+
+        simulation.set_solver(SolverType::Explicit);
+        assert_eq!(simulation.config().material(0).rest_density, density_floor);
+    }
+
+    #[test]
+    fn iterative_solvers_fall_back_to_symplectic_euler() {
+        let config = SimulationConfig {
+            integrator: Integrator::Rk2,
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        simulation.set_solver(SolverType::Pbf);
+        assert_eq!(simulation.config().integrator, Integrator::SymplecticEuler);
+        simulation.config().validate().unwrap();
+    }
+
+    /// Relative energy change over one second of free fall, where gravity is
+    /// the only force.
+    fn free_fall_energy_drift(integrator: Integrator) -> f32 {
+        let config = SimulationConfig {
+            window_size: (400, 2000),
+            particle_amt: 16,
+            // Farther apart than the smoothing radius, so they never interact.
+            particle_spacing: 40,
+            drag_coefficient: 0.0,
+            integrator,
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        let start = simulation.energy();
+        for _ in 0..60 {
+            simulation.step(1.0 / 60.0);
+        }
+        (simulation.energy() - start).abs() / start
+    }
+
+    #[test]
+    fn verlet_and_rk2_conserve_energy_better_than_euler() {
+        let euler = free_fall_energy_drift(Integrator::SymplecticEuler);
+        assert!(euler > 0.0);
+        for integrator in [Integrator::VelocityVerlet, Integrator::Rk2] {
+            let drift = free_fall_energy_drift(integrator);
+            assert!(
+                drift < euler / 100.0,
+                "{:?} drifts by {}, symplectic Euler by {}",
+                integrator,
+                drift,
+                euler
+            );
+        }
+    }
+
+    #[test]
+    fn stable_timestep_shrinks_as_particles_speed_up() {
+        let config = SimulationConfig {
+            adaptive_timestep: true,
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        let (min_timestep, max_timestep) = (
+            simulation.config().min_timestep,
+            simulation.config().max_timestep,
+        );
+        assert_eq!(simulation.context().stable_timestep(), max_timestep);
+
+        let mut previous = max_timestep;
+        for speed in [1_000.0, 2_000.0, 4_000.0] {
+            simulation.context_mut().particles_lookup.particles[0].velocity =
+                Vector::new(speed, 0.0);
+            let delta_time = simulation.context().stable_timestep();
+            assert!(
+                delta_time < previous && delta_time >= min_timestep,
+                "{} at speed {}",
+                delta_time,
+                speed
+            );
+            previous = delta_time;
+        }
+
+        simulation.context_mut().particles_lookup.particles[0].velocity = Vector::new(1e9, 0.0);
+        assert_eq!(simulation.context().stable_timestep(), min_timestep);
+    }
+
+    #[test]
+    fn adaptive_substeps_add_up_to_the_fixed_step() {
+        let config = SimulationConfig {
+            window_size: (400, 2000),
+            particle_amt: 16,
+            particle_spacing: 40,
+            drag_coefficient: 0.0,
+            adaptive_timestep: true,
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        // Fast enough to need several substeps, slow enough to stay off the walls.
+        simulation.context_mut().particles_lookup.particles[0].velocity = Vector::new(1_000.0, 0.0);
+        simulation.step_fixed(Cursor::new(
+            Vector::new(-1.0, -1.0),
+            CursorForceType::None,
+            0.0,
+        ));
+
+        let step = simulation.timestep().step;
+        assert!(simulation.delta_time() < step);
+        // Particles in free fall gain `g` times the total time simulated.
+        let gravity = simulation.config().gravity;
+        for particle in simulation.particles() {
+            assert!(
+                (particle.velocity.y - gravity * step).abs() < 1e-3,
+                "gained {} instead of {}",
+                particle.velocity.y,
+                gravity * step
+            );
+        }
+    }
+
+    #[test]
+    fn pcisph_tank_comes_to_rest() {
+        let speed = settled_max_speed(SolverType::Pcisph, 6.0);
+        assert!(speed < 5.0, "still moving at {}", speed);
+    }
+
+    #[test]
+    fn dfsph_tank_comes_to_rest() {
+        let speed = settled_max_speed(SolverType::Dfsph, 6.0);
+        assert!(speed < 5.0, "still moving at {}", speed);
+    }
+
+    #[test]
+    fn iisph_tank_comes_to_rest() {
+        let speed = settled_max_speed(SolverType::Iisph, 6.0);
+        assert!(speed < 5.0, "still moving at {}", speed);
+    }
+
+    #[test]
+    fn pbf_tank_comes_to_rest() {
+        let speed = settled_max_speed(SolverType::Pbf, 6.0);
+        assert!(speed < 5.0, "still moving at {}", speed);
+    }
+
+    /// Closest distance between two particles of a weightless drop after two
+    /// seconds under the double density model.
+    fn closest_pair_in_drop(near_pressure_constant: f32) -> f32 {
+        let config = SimulationConfig {
+            window_size: (400, 400),
+            particle_amt: 196,
+            gravity: 0.0,
+            force_model: ForceModel::DoubleDensity,
+            near_pressure_constant,
+            seed: Some(1),
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        for _ in 0..120 {
+            simulation.step(1.0 / 60.0);
+        }
+        let particles = simulation.particles();
+        let mut closest = f32::INFINITY;
+        for (i, particle) in particles.iter().enumerate() {
+            for other in &particles[i + 1..] {
+                closest = closest.min((particle.position - other.position).magnitude());
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn near_pressure_keeps_particles_from_clustering() {
+        let defaults = SimulationConfig::default();
+        let spacing = defaults.particle_spacing as f32;
+        let clustered = closest_pair_in_drop(0.0);
+        assert!(
+            clustered < spacing / 6.0,
+            "closest pair {} apart",
+            clustered
+        );
+        let spread = closest_pair_in_drop(defaults.near_pressure_constant);
+        assert!(spread > spacing / 3.0, "closest pair {} apart", spread);
+    }
+
+    /// Width of a block of fluid three seconds after it was dropped onto the
+    /// floor of a wide tank.
+    fn drop_width(surface_tension: f32) -> f32 {
+        let config = SimulationConfig {
+            window_size: (400, 200),
+            particle_amt: 196,
+            surface_tension,
+            seed: Some(1),
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        for _ in 0..180 {
+            simulation.step(1.0 / 60.0);
+        }
+        let (left, right) = simulation.particles().iter().fold(
+            (f32::INFINITY, 0.0f32),
+            |(left, right), particle| {
+                (
+                    left.min(particle.position.x),
+                    right.max(particle.position.x),
+                )
+            },
+        );
+        right - left
+    }
+
+    #[test]
+    fn surface_tension_keeps_a_drop_from_spreading() {
+        let spread = drop_width(0.0);
+        let held = drop_width(50.0);
+        assert!(
+            held < spread / 2.0,
+            "{} wide with surface tension, {} without",
+            held,
+            spread
+        );
+    }
+
+    /// Settles the default block of fluid in a narrow tank for `seconds` and
+    /// returns it together with the floor height.
+    fn settled_tank(config: SimulationConfig, seconds: f32) -> (Vec<Particle>, f32) {
+        let config = SimulationConfig {
+            window_size: (120, 200),
+            particle_amt: 256,
+            seed: Some(1),
+            ..config
+        };
+        let floor = config.window_size.1 as f32;
+        let mut simulation = Simulation::new(config, false);
+        for _ in 0..(seconds * 60.0) as usize {
+            simulation.step(1.0 / 60.0);
+        }
+        (simulation.particles().to_vec(), floor)
+    }
+
+    fn mean_density(particles: &[Particle], keep: impl Fn(&Particle) -> bool) -> f32 {
+        let densities: Vec<f32> = particles
+            .iter()
+            .filter(|particle| keep(particle))
+            .map(|particle| particle.density)
+            .collect();
+        assert!(!densities.is_empty());
+        densities.iter().sum::<f32>() / densities.len() as f32
+    }
+
+    /// Settles `config` in the tank and checks that the fluid neither expands
+    /// nor collapses: it fills the height it would packed at `particle_spacing`
+    /// and is nowhere much denser than at rest.
+    fn assert_keeps_volume(config: SimulationConfig) {
+        let rest_density = config.density_floor;
+        let spacing = config.particle_spacing as f32;
+        let (particles, floor) = settled_tank(config, 5.0);
+        let packed = particles.len() as f32 * spacing * spacing / 120.0;
+        let top = particles
+            .iter()
+            .map(|particle| particle.position.y)
+            .fold(floor, f32::min);
+        let height = floor - top;
+        assert!(
+            (0.8..1.2).contains(&(height / packed)),
+            "fluid {} high, {} when packed",
+            height,
+            packed
+        );
+        let densest = particles
+            .iter()
+            .map(|particle| particle.density)
+            .fold(0.0, f32::max);
+        assert!(
+            densest < rest_density * 1.2,
+            "density {} at rest density {}",
+            densest,
+            rest_density
+        );
+    }
+
+    #[test]
+    fn tait_tank_keeps_its_volume() {
+        assert_keeps_volume(SimulationConfig {
+            equation_of_state: EquationOfState::Tait {
+                stiffness: 40000.0,
+                exponent: 7.0,
+            },
+            ..SimulationConfig::default()
+        });
+    }
+
+    #[test]
+    fn clamped_linear_tank_keeps_its_volume() {
+        assert_keeps_volume(SimulationConfig {
+            clamp_negative_pressure: true,
+            ..SimulationConfig::default()
+        });
+    }
+
+    #[test]
+    fn fluid_keeps_its_density_at_the_floor() {
+        for force_model in [ForceModel::Pressure, ForceModel::DoubleDensity] {
+            let config = SimulationConfig {
+                force_model,
+                ..SimulationConfig::default()
+            };
+            let (particles, floor) = settled_tank(config, 10.0);
+            let at_wall = mean_density(&particles, |particle| floor - particle.position.y < 10.0);
+            let inside = mean_density(&particles, |particle| {
+                (20.0..40.0).contains(&(floor - particle.position.y))
+            });
+            assert!(
+                (0.9..1.1).contains(&(at_wall / inside)),
+                "{:?}: {} at the floor, {} inside",
+                force_model,
+                at_wall,
+                inside
+            );
+        }
+    }
+
+    #[test]
+    fn lighter_material_rises_above_heavier_one() {
+        let material = |name: &str, mass: f32| Material {
+            name: name.to_string(),
+            mass,
+            rest_density: 0.028 * mass,
+            viscosity: 500.0,
+            stiffness: 10000000.0 / mass,
+            color: (0, 0, 0),
+            fraction: 1.0,
+        };
+        for solver in [SolverType::Explicit, SolverType::Dfsph] {
+            let config = SimulationConfig {
+                solver,
+                materials: vec![material("water", 1.0), material("oil", 0.5)],
+                ..SimulationConfig::default()
+            };
+            let (particles, _) = settled_tank(config, 10.0);
+            let mean_height = |material: usize| {
+                let heights: Vec<f32> = particles
+                    .iter()
+                    .filter(|particle| particle.material == material)
+                    .map(|particle| particle.position.y)
+                    .collect();
+                heights.iter().sum::<f32>() / heights.len() as f32
+            };
+            let (water, oil) = (mean_height(0), mean_height(1));
+            assert!(
+                water > oil + 15.0,
+                "{:?}: water at {}, oil at {}",
+                solver,
+                water,
+                oil
+            );
+        }
+    }
+
+    /// Drops a circle of `density` into a deep tank and returns where its centre
+    /// and the fluid surface away from it end up.
+    fn settled_body(density: f32) -> (f32, f32) {
+        let config = SimulationConfig {
+            window_size: (200, 300),
+            particle_amt: 800,
+            seed: Some(1),
+            rigid_bodies: vec![RigidBodyConfig {
+                shape: BodyShape::Circle { radius: 25.0 },
+                position: (100.0, 30.0),
+                density,
+                angle: 0.0,
+                velocity: (0.0, 0.0),
+                angular_velocity: 0.0,
+            }],
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        for _ in 0..480 {
+            simulation.step(1.0 / 60.0);
+        }
+        let surface = simulation
+            .particles()
+            .iter()
+            .filter(|particle| (particle.position.x - 100.0).abs() > 40.0)
+            .map(|particle| particle.position.y)
+            .fold(f32::MAX, f32::min);
+        (simulation.rigid_bodies()[0].position.y, surface)
+    }
+
+    #[test]
+    fn lighter_body_floats() {
+        let (body, surface) = settled_body(0.01);
+        assert!(body < surface, "body at {}, surface at {}", body, surface);
+    }
+
+    #[test]
+    fn heavier_body_sinks() {
+        let (body, surface) = settled_body(0.06);
+        assert!(
+            body - 25.0 > surface,
+            "body at {}, surface at {}",
+            body,
+            surface
+        );
+    }
+
+    /// The wall samples hold the fluid about one spacing away, as more fluid
+    /// below it would, rather than the clamp at `particle_radius`.
+    #[test]
+    fn explicit_fluid_rests_on_the_floor() {
+        let config = SimulationConfig::default();
+        let radius = config.particle_radius as f32;
+        let spacing = config.particle_spacing as f32;
+        let (particles, floor) = settled_tank(config, 10.0);
+        let lowest = particles
+            .iter()
+            .map(|particle| particle.position.y)
+            .fold(0.0, f32::max);
+        let gap = floor - lowest;
+        assert!(
+            radius + 1.0 < gap && gap < spacing,
+            "resting {} above the floor",
+            gap
+        );
+    }
+
+    /// Speed left after a particle slides along a rough floor for half a
+    /// second in steps of `delta_time`.
+    fn sliding_speed(delta_time: f32) -> f32 {
+        let config = SimulationConfig {
+            window_size: (400, 200),
+            particle_amt: 1,
+            walls: Walls {
+                bottom: WallMode::Solid {
+                    restitution: 0.0,
+                    friction: 0.05,
+                },
+                ..Walls::default()
+            },
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        let particle = &mut simulation.context_mut().particles_lookup.particles[0];
+        particle.position = Vector::new(100.0, 194.0);
+        particle.velocity = Vector::new(50.0, 0.0);
+        for _ in 0..(0.5 / delta_time).round() as usize {
+            simulation.step(delta_time);
+        }
+        simulation.particles()[0].velocity.x
+    }
+
+    #[test]
+    fn wall_friction_does_not_depend_on_the_step_rate() {
+        let coarse = sliding_speed(1.0 / 60.0);
+        let fine = sliding_speed(1.0 / 240.0);
+        assert!(coarse < 25.0, "slid on at {}", coarse);
+        assert!(
+            (coarse - fine).abs() < 0.1 * coarse,
+            "{} at 60 Hz, {} at 240 Hz",
+            coarse,
+            fine
+        );
+    }
+
+    #[test]
+    fn random_placement_gives_up_on_a_crowded_window() {
+        let config = SimulationConfig {
+            window_size: (40, 40),
+            particle_amt: 55,
+            particle_radius: 2,
+            ..SimulationConfig::default()
+        };
+        config.validate().unwrap();
+        let simulation = Simulation::new(config, true);
+        assert_eq!(simulation.particle_count(), 55);
+    }
+
+    /// A single particle in a tall tank fed by a nozzle at the top emitting
+    /// `rate` particles per second.
+    fn fed_tank(rate: f32, max_particles: usize, bottom: WallMode) -> Simulation {
+        let config = SimulationConfig {
+            window_size: (200, 300),
+            particle_amt: 1,
+            max_particles,
+            emitters: vec![EmitterConfig {
+                shape: EmitterShape::Nozzle {
+                    position: (100.0, 20.0),
+                    width: 30.0,
+                },
+                velocity: (0.0, 50.0),
+                rate,
+                material: 0,
+            }],
+            walls: Walls {
+                bottom,
+                ..Walls::default()
+            },
+            ..SimulationConfig::default()
+        };
+        Simulation::new(config, false)
+    }
+
+    #[test]
+    fn emitters_follow_their_rate() {
+        let mut simulation = fed_tank(90.0, 500, WallMode::default());
+        for _ in 0..60 {
+            simulation.step(1.0 / 60.0);
+        }
+        assert_eq!(simulation.particle_count(), 1 + 90);
+    }
+
+    #[test]
+    fn emitters_stop_at_max_particles() {
+        let mut simulation = fed_tank(600.0, 100, WallMode::default());
+        for _ in 0..60 {
+            simulation.step(1.0 / 60.0);
+        }
+        assert_eq!(simulation.particle_count(), 100);
+    }
+
+    #[test]
+    fn particles_leave_through_an_open_floor() {
+        let mut simulation = fed_tank(90.0, 500, WallMode::Open);
+        for _ in 0..120 {
+            simulation.step(1.0 / 60.0);
+        }
+        assert!(simulation.particle_count() > 0);
+
+        simulation.context_mut().emitters[0].rate = 0.0;
+        for _ in 0..240 {
+            simulation.step(1.0 / 60.0);
+        }
+        assert_eq!(simulation.particle_count(), 0);
+    }
+
+    #[test]
+    fn drains_remove_the_particles_entering_them() {
+        let config = SimulationConfig {
+            window_size: (120, 200),
+            particle_amt: 256,
+            drains: vec![Obstacle::Box {
+                center: (60.0, 190.0),
+                size: (120.0, 20.0),
+            }],
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        for _ in 0..240 {
+            simulation.step(1.0 / 60.0);
+        }
+        assert_eq!(simulation.particle_count(), 0);
+    }
+
+    #[test]
+    fn add_and_remove_reject_unknown_particles() {
+        let mut simulation = Simulation::new(SimulationConfig::default(), false);
+        let count = simulation.particle_count();
+        assert!(!simulation.add_particle(Vector::new(600.0, 100.0), Vector::zero(), 1));
+        assert_eq!(simulation.remove_particle(count), None);
+        assert_eq!(simulation.particle_count(), count);
+    }
+
+    #[test]
+    fn particles_added_and_removed_between_steps_are_refiled() {
+        let mut simulation = Simulation::new(SimulationConfig::default(), false);
+        simulation.step(1.0 / 60.0);
+
+        let point = Vector::new(600.0, 100.0);
+        assert!(simulation.add_particle(point, Vector::zero(), 0));
+        let added = simulation.particle_count() - 1;
+        let lookup = &simulation.context().particles_lookup;
+        assert!(lookup.query_around(point).any(|(index, _)| index == added));
+
+        let removed = simulation.remove_particle(0).unwrap();
+        let lookup = &simulation.context().particles_lookup;
+        assert!(lookup
+            .query_around(removed.predicted_position)
+            .all(|(index, particle)| index < simulation.particle_count()
+                && particle.predicted_position != removed.predicted_position));
+        assert!(lookup
+            .query_around(point)
+            .any(|(index, _)| index == added - 1));
+
+        for _ in 0..10 {
+            simulation.step(1.0 / 60.0);
+        }
+    }
+
+    #[test]
+    fn removing_a_particle_rebuilds_the_periodic_ghosts() {
+        let config = SimulationConfig {
+            walls: Walls {
+                left: WallMode::Periodic,
+                right: WallMode::Periodic,
+                ..Walls::default()
+            },
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(config, false);
+        let first = simulation.particle_count();
+        simulation.add_particle(Vector::new(2.0, 20.0), Vector::zero(), 0);
+        simulation.add_particle(Vector::new(3.0, 150.0), Vector::zero(), 0);
+
+        let across_seam = |simulation: &Simulation, y: f32| -> Vec<(usize, Vector)> {
+            simulation
+                .context()
+                .particles_lookup
+                .query_around(Vector::new(1199.0, y))
+                .map(|(index, particle)| (index, particle.predicted_position))
+                .collect()
+        };
+        assert_eq!(
+            across_seam(&simulation, 20.0),
+            vec![(first, Vector::new(1202.0, 20.0))]
+        );
+
+        simulation.remove_particle(first).unwrap();
+        assert!(across_seam(&simulation, 20.0).is_empty());
+        assert_eq!(
+            across_seam(&simulation, 150.0),
+            vec![(first, Vector::new(1203.0, 150.0))]
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn thread_count_does_not_change_the_result() {
+        for solver in [
+            SolverType::Explicit,
+            SolverType::Pcisph,
+            SolverType::Dfsph,
+            SolverType::Pbf,
+            SolverType::Iisph,
+        ] {
+            let run_with = |threads| {
+                let config = SimulationConfig {
+                    particle_amt: 300,
+                    seed: Some(7),
+                    threads,
+                    solver,
+                    ..SimulationConfig::default()
+                };
+                let mut simulation = Simulation::new(config, true);
+                run(&mut simulation);
+                simulation.particles().to_vec()
+            };
+            assert_eq!(run_with(1), run_with(4), "{:?}", solver);
+        }
+    }
+}
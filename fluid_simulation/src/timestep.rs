@@ -0,0 +1,99 @@
+/// Fixed-timestep accumulator that turns variable frame times into a whole
+/// number of physics steps.
+///
+/// Every step has the same length regardless of how fast frames are rendered.
+/// Leftover time is carried over to the next frame, and at most `max_steps`
+/// steps are run per frame; anything beyond that is dropped so a slow frame
+/// cannot snowball into ever longer catch-up frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedTimestep {
+    pub step: f32,
+    pub substeps: u32,
+    pub max_steps: u32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    pub fn new(step: f32, substeps: u32, max_steps: u32) -> FixedTimestep {
+        FixedTimestep {
+            step,
+            substeps: substeps.max(1),
+            max_steps: max_steps.max(1),
+            accumulator: 0.0,
+        }
+    }
+
+    /// Adds `frame_time` seconds and returns how many fixed steps are due.
+    pub fn advance(&mut self, frame_time: f32) -> u32 {
+        self.accumulator += frame_time.max(0.0);
+
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_steps {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if steps == self.max_steps {
+            self.accumulator = self.accumulator.min(self.step);
+        }
+        steps
+    }
+
+    /// Length of one substep, the `delta_time` actually handed to the physics.
+    pub fn substep(&self) -> f32 {
+        self.step / self.substeps as f32
+    }
+
+    /// Fraction of a step left in the accumulator, for interpolating rendering.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.step
+    }
+
+    pub fn reset(&mut self) {
+        self.accumulator = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leftover_time_carries_over_to_the_next_frame() {
+        let mut timestep = FixedTimestep::new(0.25, 1, 8);
+        assert_eq!(timestep.advance(0.375), 1);
+        assert_eq!(timestep.alpha(), 0.5);
+        assert_eq!(timestep.advance(0.125), 1);
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.advance(0.125), 0);
+        assert_eq!(timestep.alpha(), 0.5);
+    }
+
+    #[test]
+    fn slow_frames_are_clamped_to_max_steps() {
+        let mut timestep = FixedTimestep::new(0.25, 1, 4);
+        assert_eq!(timestep.advance(10.0), 4);
+        // The backlog is dropped, at most one step is kept for the next frame.
+        assert_eq!(timestep.alpha(), 1.0);
+        assert_eq!(timestep.advance(0.0), 1);
+        assert_eq!(timestep.advance(0.0), 0);
+    }
+
+    #[test]
+    fn negative_frame_times_are_ignored() {
+        let mut timestep = FixedTimestep::new(0.25, 1, 8);
+        assert_eq!(timestep.advance(0.125), 0);
+        assert_eq!(timestep.advance(-1.0), 0);
+        assert_eq!(timestep.alpha(), 0.5);
+        assert_eq!(timestep.advance(0.125), 1);
+    }
+
+    #[test]
+    fn substeps_split_the_step() {
+        let timestep = FixedTimestep::new(0.25, 4, 8);
+        assert_eq!(timestep.substep(), 0.0625);
+        assert_eq!(
+            FixedTimestep::new(0.25, 0, 0),
+            FixedTimestep::new(0.25, 1, 1)
+        );
+    }
+}